@@ -1,4 +1,4 @@
-import { PacketReader, PacketWriter } from '../packet.js'
+import { PacketReader, PacketWriter, PROTOCOL_VERSION, REJECT_REASONS } from '../packet.js'
 
 let rootWsUrl;
 if (location.protocol === 'https:') {
@@ -14,9 +14,9 @@ rootWsUrl += location.host;
 let ws;
 
 /**
-  * @type {number}
+  * @type {string}
   */
-let id;
+let joinCode;
 
 /**
   * @type {{ duration: number, scorePoints: { name: string, category: string, points: number }[] }}
@@ -30,15 +30,40 @@ let gamePaused = false;
 let pauseStarted;
 let pausedTime = 0;
 
+let scoringLocked = false;
+let outdated = false;
+
+/**
+  * a random key for the field's scoreboard that stays the same across matches, so invites keep working between them
+  * @type {string}
+  */
+let scoreboardKey;
+
+document.getElementById('fieldInput').value = localStorage.getItem('field') ?? '1';
+
+/**
+  * categories of invites that have been requested but not created yet
+  * @type {string[][]}
+  */
+const pendingInviteCategories = [];
+
 const bluePointsSpan = document.getElementById('bluePoints');
 const redPointsSpan = document.getElementById('redPoints');
 
 const BLUE_ID = 0;
 const RED_ID = 1;
 
+const REF_ROLES = ['Scorer', 'Penalty', 'Head'];
+
 let bluePoints = 0;
 let redPoints = 0;
 
+/**
+  * official number of times each score point was scored, by team
+  * @type {{ [team: number]: { [scoreId: number]: number } }}
+  */
+const scoreCounts = { [BLUE_ID]: {}, [RED_ID]: {} };
+
 const blueTeams = [];
 const redTeams = [];
 
@@ -57,16 +82,51 @@ document.getElementById('startBtn').addEventListener('click', _ => {
 });
 
 document.getElementById('endBtn').addEventListener('click', event => {
-  if (!startedTime) return;
+  if (!startedTime && !gameEnded) return;
 
   if (gameEnded) {
-    event.target.disabled = true;
+    if (document.querySelector('#discrepancies tbody').childElementCount > 0) {
+      alert('Resolve every discrepancy before revealing the score');
+      return;
+    }
+    if (document.querySelector('#pendingScores tbody').childElementCount > 0) {
+      alert('Review every pending score before revealing the score');
+      return;
+    }
+    // the page only switches to revealed once the server confirms it
     revealScore();
   } else {
     endGame();
   }
 });
 
+document.getElementById('reopenBtn').addEventListener('click', event => {
+  reopenScore();
+  event.target.style.display = 'none';
+  document.getElementById('endBtn').disabled = false;
+});
+
+document.getElementById('adjustForm').addEventListener('submit', event => {
+  event.preventDefault();
+
+  const count = parseInt(event.target.count.value);
+  if (!(count >= 0)) return;
+  const adjustment = getAdjustment();
+  if (!adjustment) return;
+
+  setScoreCount(adjustment.team, adjustment.scoreId, count, adjustment.reason);
+});
+
+document.getElementById('adjustAddBtn').addEventListener('click', _ => {
+  const adjustment = getAdjustment();
+  if (adjustment) adjustScore(adjustment.team, adjustment.scoreId, false, adjustment.reason);
+});
+
+document.getElementById('adjustRemoveBtn').addEventListener('click', _ => {
+  const adjustment = getAdjustment();
+  if (adjustment) adjustScore(adjustment.team, adjustment.scoreId, true, adjustment.reason);
+});
+
 document.getElementById('pauseBtn').addEventListener('click', event => {
   if (!startedTime) return;
   if (gamePaused) {
@@ -78,6 +138,54 @@ document.getElementById('pauseBtn').addEventListener('click', event => {
   }
 });
 
+document.getElementById('setRefPinBtn').addEventListener('click', _ => {
+  const pinInput = document.getElementById('refPinInput');
+  const pin = pinInput.value.trim();
+  if (!/^[0-9]{4,8}$/.test(pin)) {
+    alert('PIN must be 4 to 8 digits');
+    return;
+  }
+
+  pinInput.value = '';
+  setRefPin(pin);
+});
+
+document.getElementById('generateRefPinBtn').addEventListener('click', _ => setRefPin(null));
+
+document.getElementById('inviteForm').addEventListener('submit', event => {
+  event.preventDefault();
+
+  const team = parseInt(event.target.team.value);
+  const expiresIn = parseInt(event.target.expiresIn.value) * 60 * 60;
+  if (!(expiresIn > 0)) return;
+  const categories = [...document.querySelectorAll('#inviteCategories input:checked')].map(input => input.value);
+
+  createInvite(team, categories, expiresIn);
+});
+
+document.getElementById('lockScoringBtn').addEventListener('click', event => {
+  setScoringLocked(!scoringLocked);
+  event.target.innerText = scoringLocked ? 'Unlock Scoring' : 'Lock Scoring';
+});
+
+document.getElementById('scoreWhilePausedCheckbox').addEventListener('change', event => {
+  setScoreWhilePaused(event.target.checked);
+});
+
+document.getElementById('gracePeriodInput').addEventListener('change', event => {
+  const seconds = parseInt(event.target.value);
+  if (!(seconds >= 0 && seconds <= 60)) return;
+  setGracePeriod(seconds);
+});
+
+document.getElementById('consensusModeSelect').addEventListener('input', event => {
+  setConsensusMode(parseInt(event.target.value));
+});
+
+document.getElementById('displayModeSelect').addEventListener('input', event => {
+  setDisplayMode(parseInt(event.target.value));
+});
+
 document.getElementById('hostInfoForm').addEventListener('submit', event => {
   event.preventDefault();
 
@@ -95,7 +203,7 @@ document.getElementById('hostInfoForm').addEventListener('submit', event => {
   } else {
     return;
   }
-  host(event.target.matchNumber.value, gameType, data);
+  host(event.target.matchNumber.value, event.target.field.value.trim() || '1', gameType, data);
 });
 
 document.getElementById('gameTypeSelect').addEventListener('input', _ => {
@@ -196,7 +304,7 @@ function getGameData() {
   const form = document.getElementById('hostInfoForm');
   const duration = (form.durationMin.value ?? 0) * 60 + parseInt(form.durationSecs.value ?? 0);
   const scorePoints = [];
-  let length = 2;
+  let length = 6;
 
   for (const row of document.getElementsByClassName('scoreTableRow')) {
     const name = row.getElementsByClassName('name')[0].value;
@@ -207,7 +315,7 @@ function getGameData() {
 
     scorePoints.push({ name, category, points });
     const encoder = new TextEncoder();
-    length += 17 + encoder.encode(name).length + encoder.encode(category).length;
+    length += 9 + encoder.encode(name).length + encoder.encode(category).length;
   }
 
   return [{ duration, scorePoints }, length];
@@ -344,12 +452,18 @@ function retrieveBuiltinGames(builtinGames) {
 
 /**
   * @param {number} matchNumber
+  * @param {string} field
   * @param {'builtin' | 'custom'} gameType
   */
-function host(matchNumber, gameType, data) {
+function host(matchNumber, field, gameType, data) {
   document.getElementById('prehost').style.display = 'none';
   document.getElementById('loadingDiv').style.display = 'block';
 
+  // each field has its own scoreboard, so invites for one field can't join a match on another
+  localStorage.setItem('field', field);
+  scoreboardKey = localStorage.getItem(`scoreboardKey:${field}`) ?? [...crypto.getRandomValues(new Uint8Array(16))].map(byte => byte.toString(16).padStart(2, '0')).join('');
+  localStorage.setItem(`scoreboardKey:${field}`, scoreboardKey);
+
   ws = new WebSocket(`${rootWsUrl}/ws/host`);
 
   ws.addEventListener('open', _ => {
@@ -357,32 +471,35 @@ function host(matchNumber, gameType, data) {
     console.log(gameType);
     console.log(data);
 
-    let blueNameSize = blueTeams.length * 8;
+    let blueNameSize = blueTeams.length * 4;
     for (const name of blueTeams) {
       blueNameSize += name.length;
     }
 
-    let redNameSize = redTeams.length * 8;
+    let redNameSize = redTeams.length * 4;
     for (const name of redTeams) {
       redNameSize += name.length;
     }
-    const nameSize = 16 + blueNameSize + redNameSize;
+    const nameSize = 8 + blueNameSize + redNameSize;
+    const keySize = 4 + scoreboardKey.length;
 
     let writer;
     if (gameType === 'builtin') {
-      writer = new PacketWriter(nameSize + 12);
+      writer = new PacketWriter(nameSize + keySize + 10);
     } else if (gameType === 'custom') {
-      writer = new PacketWriter(nameSize + 4 + data[1]);
+      writer = new PacketWriter(nameSize + keySize + 6 + data[1]);
     }
 
     writer.writeUint8(4);
+    writer.writeUint16(PROTOCOL_VERSION);
+    writer.writeString(scoreboardKey);
     writer.writeUint16(matchNumber);
     writer.writeStringArray(blueTeams);
     writer.writeStringArray(redTeams);
 
     if (gameType === 'builtin') {
       writer.writeUint8(0);
-      writer.writeUint64(BigInt(data));
+      writer.writeUint32(data);
     } else if (gameType === 'custom') {
       writer.writeUint8(1);
       writer.writeGameData(data[0]);
@@ -390,7 +507,8 @@ function host(matchNumber, gameType, data) {
     ws.send(writer.get());
   });
 
-  ws.addEventListener('close', _ => {
+  ws.addEventListener('close', event => {
+    if (event.reason) document.getElementById('disconnectReason').innerText = event.reason;
     disconnect();
 
     console.log('connection closed');
@@ -405,14 +523,23 @@ function host(matchNumber, gameType, data) {
   ws.addEventListener('message', async event => {
     const reader = new PacketReader(await event.data.arrayBuffer());
     switch (reader.readUint8()) {
+      // Outdated
+      case 255: {
+        console.log(`server is on protocol version ${reader.readUint16()}, this page is on ${PROTOCOL_VERSION}`);
+        outdated = true;
+
+        break;
+      };
+
       // SessionInfo
       case 0: {
         console.log('session info');
 
-        id = reader.readUint32();
+        joinCode = reader.readStringLength();
+        document.getElementById('refPin').innerText = reader.readStringLength();
         gameInfo = reader.readGameInfo();
 
-        console.log(`id: ${id}`);
+        console.log(`join code: ${joinCode}`);
         console.log(`duration: ${gameInfo.duration}s`);
         console.table(gameInfo.scorePoints);
 
@@ -426,11 +553,138 @@ function host(matchNumber, gameType, data) {
         console.log('score type');
 
         const team = reader.readUint8();
-        const scoreId = reader.readUint8();
+        const scoreId = reader.readUint16();
         const undo = reader.readBool();
+        const late = reader.readBool();
 
-        score(team, scoreId, undo);
-      }
+        score(team, scoreId, undo, late);
+
+        break;
+      };
+
+      // RefJoined
+      case 2: {
+        const refId = reader.readUint32();
+        const name = reader.readStringLength();
+        const role = reader.readUint8();
+        const team = reader.readUint8();
+        const categories = reader.readStringArray();
+
+        console.log(`ref ${refId} (${name}) joined`);
+
+        addRef(refId, name, role, team, categories);
+
+        break;
+      };
+
+      // RefLeft
+      case 3: {
+        const refId = reader.readUint32();
+
+        console.log(`ref ${refId} left`);
+
+        document.getElementById(`ref${refId}`)?.remove();
+
+        break;
+      };
+
+      // RefPin
+      case 4: {
+        document.getElementById('refPin').innerText = reader.readStringLength();
+
+        break;
+      };
+
+      // Invite
+      case 5: {
+        const inviteId = reader.readUint32();
+        const team = reader.readUint8();
+        const expiresAt = Number(reader.readUint64());
+        const token = reader.readStringLength();
+
+        console.log(`created invite ${inviteId}`);
+
+        const categories = pendingInviteCategories.shift() ?? [];
+        saveInvites([...savedInvites(), { inviteId, team, categories, expiresAt, token }]);
+        addInvite(inviteId, team, categories, expiresAt, token);
+
+        break;
+      };
+
+      // PendingScore
+      case 6: {
+        const pendingId = reader.readUint32();
+        const refId = reader.readUint32();
+        const team = reader.readUint8();
+        const scoreId = reader.readUint16();
+        const undo = reader.readBool();
+        const late = reader.readBool();
+
+        console.log(`score ${pendingId} is pending review`);
+
+        addPendingScore(pendingId, refId, team, scoreId, undo, late);
+
+        break;
+      };
+
+      // ScoreCounts
+      case 7: {
+        const team = reader.readUint8();
+        const scoreId = reader.readUint16();
+        const count = reader.readUint32();
+        const disputed = reader.readBool();
+        const refIds = reader.readUint32Array();
+        const counts = reader.readUint32Array();
+        const late = reader.readBool();
+
+        applyScoreCount(team, scoreId, count, late);
+        updateDiscrepancy(team, scoreId, disputed, refIds, counts);
+
+        break;
+      };
+
+      // ScoreAdjusted
+      case 8: {
+        const team = reader.readUint8();
+        const scoreId = reader.readUint16();
+        const count = reader.readUint32();
+        const reason = reader.readStringLength();
+        const time = Number(reader.readUint64());
+
+        console.log(`adjusted score ${scoreId} to ${count}`);
+
+        applyScoreCount(team, scoreId, count);
+        updateDiscrepancy(team, scoreId, false, [], []);
+        addAdjustment(team, scoreId, count, reason, time);
+
+        break;
+      };
+
+      // Rejected
+      case 9: {
+        const reason = REJECT_REASONS[reader.readUint8()] ?? 'unknown reason';
+        console.warn(`the server rejected a packet: ${reason}`);
+        alert(`Not done: ${reason}`);
+
+        break;
+      };
+
+      // ScoreRevealed
+      case 11: {
+        console.log('revealed score');
+        startedTime = null;
+        document.getElementById('endBtn').disabled = true;
+        document.getElementById('reopenBtn').style.display = 'inline';
+
+        break;
+      };
+
+      // Malformed
+      case 10: {
+        console.warn(`the server could not read a packet: ${reader.readStringLength()}`);
+
+        break;
+      };
     }
   });
 }
@@ -439,13 +693,58 @@ function init() {
   document.getElementById('loadingDiv').style.display = 'none';
   document.getElementById('main').style.display = 'block';
 
-  document.getElementById('hostId').innerText = id.toString(36);
+  document.getElementById('joinCode').innerText = joinCode;
+
+  const invites = savedInvites();
+  saveInvites(invites);
+  for (const { inviteId, team, categories, expiresAt, token } of invites) {
+    addInvite(inviteId, team, categories, expiresAt, token);
+  }
 
-  document.getElementById('viewLink').href = `/view?id=${id.toString(36)}`;
+  const inviteCategories = document.getElementById('inviteCategories');
+  const reviewCategories = document.getElementById('reviewCategories');
+  for (const category of new Set(gameInfo.scorePoints.map(scorePoint => scorePoint.category))) {
+    inviteCategories.appendChild(categoryCheckbox(category));
+
+    const reviewLabel = categoryCheckbox(category);
+    reviewLabel.firstChild.addEventListener('change', _ => {
+      setReviewCategories([...reviewCategories.querySelectorAll('input:checked')].map(input => input.value));
+    });
+    reviewCategories.appendChild(reviewLabel);
+  }
+
+  const adjustScoreSelect = document.getElementById('adjustScoreSelect');
+  gameInfo.scorePoints.forEach((scorePoint, scoreId) => {
+    const option = document.createElement('option');
+    option.value = scoreId;
+    option.innerText = scorePoint.name;
+    adjustScoreSelect.appendChild(option);
+  });
+
+  document.getElementById('viewLink').href = `/view?code=${joinCode}`;
+  for (const overlayLink of document.getElementsByClassName('overlayLink')) {
+    overlayLink.href = `/overlay/${joinCode}/${overlayLink.dataset.layout}`;
+  }
+  for (const qrCode of document.getElementsByClassName('qrCode')) {
+    qrCode.src = `/api/qr/${joinCode}/${qrCode.dataset.target}`;
+  }
 
   startUpdateTimeInterval();
 }
 
+/**
+  * @param {string} category
+  */
+function categoryCheckbox(category) {
+  const label = document.createElement('label');
+  const checkbox = document.createElement('input');
+  checkbox.type = 'checkbox';
+  checkbox.value = category;
+  label.appendChild(checkbox);
+  label.appendChild(document.createTextNode(`${category} `));
+  return label;
+}
+
 function startUpdateTimeInterval() {
   const updateTimeIntervalId = setInterval(() => {
     if (gamePaused) return;
@@ -498,8 +797,96 @@ function revealScore() {
   const writer = new PacketWriter(1);
   writer.writeUint8(5);
   ws.send(writer.get());
+}
+
+function reopenScore() {
+  const writer = new PacketWriter(1);
+  writer.writeUint8(19);
+  ws.send(writer.get());
+}
+
+/**
+  * reads the alliance, score point, and reason of the adjust score form
+  * @returns {{ team: 0 | 1, scoreId: number, reason: string }?}
+  */
+function getAdjustment() {
+  const form = document.getElementById('adjustForm');
+  const reason = form.reason.value.trim();
+  if (!reason) {
+    alert('Give a reason for the adjustment');
+    return null;
+  }
+
+  return { team: parseInt(form.team.value), scoreId: parseInt(form.scoreId.value), reason };
+}
 
-  startedTime = null;
+/**
+  * @param {0 | 1} team
+  * @param {number} scoreId
+  * @param {number} count
+  * @param {string} reason
+  */
+function setScoreCount(team, scoreId, count, reason) {
+  const writer = new PacketWriter(12 + new TextEncoder().encode(reason).length);
+  writer.writeUint8(17);
+  writer.writeUint8(team);
+  writer.writeUint16(scoreId);
+  writer.writeUint32(count);
+  writer.writeString(reason);
+  ws.send(writer.get());
+}
+
+/**
+  * @param {0 | 1} team
+  * @param {number} scoreId
+  * @param {boolean} undo
+  * @param {string} reason
+  */
+function adjustScore(team, scoreId, undo, reason) {
+  const writer = new PacketWriter(9 + new TextEncoder().encode(reason).length);
+  writer.writeUint8(18);
+  writer.writeUint8(team);
+  writer.writeUint16(scoreId);
+  writer.writeBool(undo);
+  writer.writeString(reason);
+  ws.send(writer.get());
+}
+
+/**
+  * @param {0 | 1} team
+  * @param {number} scoreId
+  * @param {number} count
+  * @param {string} reason
+  * @param {number} time
+  */
+function addAdjustment(team, scoreId, count, reason, time) {
+  const teamString = team === BLUE_ID ? 'blue' : 'red';
+
+  const row = document.createElement('tr');
+  row.classList.add(teamString);
+
+  const teamCell = document.createElement('td');
+  teamCell.innerText = teamString;
+
+  const scoredCell = document.createElement('td');
+  scoredCell.innerText = gameInfo.scorePoints[scoreId].name;
+
+  const countCell = document.createElement('td');
+  countCell.innerText = count;
+
+  const reasonCell = document.createElement('td');
+  reasonCell.innerText = reason;
+
+  const timeCell = document.createElement('td');
+  timeCell.innerText = new Date(time * 1000).toLocaleTimeString();
+
+  row.appendChild(teamCell);
+  row.appendChild(scoredCell);
+  row.appendChild(countCell);
+  row.appendChild(reasonCell);
+  row.appendChild(timeCell);
+
+  document.querySelector('#adjustments tbody').appendChild(row);
 }
 
 function pauseGame() {
@@ -524,7 +911,409 @@ function unpauseGame() {
   pausedTime += timePaused;
 }
 
+/**
+  * @param {number} refId
+  * @param {string} name
+  * @param {number} role
+  * @param {0 | 1} team
+  * @param {string[]} categories
+  */
+function addRef(refId, name, role, team, categories) {
+  const teamString = team === BLUE_ID ? 'blue' : 'red';
+
+  const row = document.createElement('tr');
+  row.id = `ref${refId}`;
+  row.classList.add(teamString);
+
+  const nameCell = document.createElement('td');
+  nameCell.innerText = name;
+
+  const roleCell = document.createElement('td');
+  roleCell.innerText = REF_ROLES[role];
+
+  const teamCell = document.createElement('td');
+  teamCell.innerText = teamString;
+
+  const categoriesCell = document.createElement('td');
+  categoriesCell.innerText = formatCategories(categories);
+
+  const actionsCell = document.createElement('td');
+  const categoriesInput = document.createElement('input');
+  categoriesInput.type = 'button';
+  categoriesInput.value = 'Categories';
+  categoriesInput.addEventListener('click', _ => {
+    const input = prompt(`Categories ${name} can score, separated by commas (leave empty for every category)`, categories.join(', '));
+    if (input === null) return;
+    categories = input.split(',').map(category => category.trim()).filter(category => category.length > 0);
+    setRefCategories(refId, categories);
+    categoriesCell.innerText = formatCategories(categories);
+  });
+  const kickInput = document.createElement('input');
+  kickInput.type = 'button';
+  kickInput.value = 'Kick';
+  kickInput.addEventListener('click', _ => kickRef(refId, false));
+  const banInput = document.createElement('input');
+  banInput.type = 'button';
+  banInput.value = 'Ban';
+  banInput.addEventListener('click', _ => {
+    if (confirm(`Ban ${name} from rejoining this session?`)) kickRef(refId, true);
+  });
+  actionsCell.appendChild(categoriesInput);
+  actionsCell.appendChild(kickInput);
+  actionsCell.appendChild(banInput);
+
+  row.appendChild(nameCell);
+  row.appendChild(roleCell);
+  row.appendChild(teamCell);
+  row.appendChild(categoriesCell);
+  row.appendChild(actionsCell);
+
+  // a ref whose device joined again before their old connection closed keeps their place
+  const oldRow = document.getElementById(`ref${refId}`);
+  if (oldRow) oldRow.replaceWith(row);
+  else document.querySelector('#refRoster tbody').appendChild(row);
+}
+
+/**
+  * @param {number} refId
+  * @param {string[]} categories
+  */
+function setRefCategories(refId, categories) {
+  const encoder = new TextEncoder();
+  let size = 9 + categories.length * 4;
+  for (const category of categories) {
+    size += encoder.encode(category).length;
+  }
+
+  const writer = new PacketWriter(size);
+  writer.writeUint8(12);
+  writer.writeUint32(refId);
+  writer.writeStringArray(categories);
+  ws.send(writer.get());
+}
+
+/**
+  * @param {string[]} categories
+  */
+function formatCategories(categories) {
+  return categories.length === 0 ? 'all' : categories.join(', ');
+}
+
+/**
+  * @param {number} refId
+  * @param {boolean} ban
+  */
+function kickRef(refId, ban) {
+  const writer = new PacketWriter(6);
+  writer.writeUint8(7);
+  writer.writeUint32(refId);
+  writer.writeBool(ban);
+  ws.send(writer.get());
+}
+
+/**
+  * @param {string?} pin
+  */
+function setRefPin(pin) {
+  const writer = new PacketWriter(pin === null ? 2 : 6 + pin.length);
+  writer.writeUint8(9);
+  writer.writeBool(pin !== null);
+  if (pin !== null) writer.writeString(pin);
+  ws.send(writer.get());
+}
+
+/**
+  * @param {0 | 1} team
+  * @param {string[]} categories
+  * @param {number} expiresIn
+  */
+function createInvite(team, categories, expiresIn) {
+  const encoder = new TextEncoder();
+  let size = 14 + categories.length * 4;
+  for (const category of categories) {
+    size += encoder.encode(category).length;
+  }
+
+  const writer = new PacketWriter(size);
+  writer.writeUint8(10);
+  writer.writeUint8(team);
+  writer.writeStringArray(categories);
+  writer.writeUint64(BigInt(expiresIn));
+  ws.send(writer.get());
+
+  pendingInviteCategories.push(categories);
+}
+
+/**
+  * @param {number} inviteId
+  */
+function revokeInvite(inviteId) {
+  const writer = new PacketWriter(5);
+  writer.writeUint8(11);
+  writer.writeUint32(inviteId);
+  ws.send(writer.get());
+}
+
+/**
+  * invites this scoreboard created that haven't expired or been revoked, kept so they can still be revoked in later matches
+  * @returns {{ inviteId: number, team: 0 | 1, categories: string[], expiresAt: number, token: string }[]}
+  */
+function savedInvites() {
+  const now = Date.now() / 1000;
+  return JSON.parse(localStorage.getItem(`invites:${scoreboardKey}`) ?? '[]').filter(invite => invite.expiresAt > now);
+}
+
+/**
+  * @param {{ inviteId: number, team: 0 | 1, categories: string[], expiresAt: number, token: string }[]} invites
+  */
+function saveInvites(invites) {
+  localStorage.setItem(`invites:${scoreboardKey}`, JSON.stringify(invites));
+}
+
+/**
+  * @param {number} inviteId
+  * @param {0 | 1} team
+  * @param {string[]} categories
+  * @param {number} expiresAt
+  * @param {string} token
+  */
+function addInvite(inviteId, team, categories, expiresAt, token) {
+  const teamString = team === BLUE_ID ? 'blue' : 'red';
+  // the join code and alliance are looked up from the invite, so the link works for every match on this scoreboard
+  const link = `${location.origin}/join?invite=${token}`;
+
+  const row = document.createElement('tr');
+  row.classList.add(teamString);
+
+  const teamCell = document.createElement('td');
+  teamCell.innerText = teamString;
+
+  const categoriesCell = document.createElement('td');
+  categoriesCell.innerText = formatCategories(categories);
+
+  const expiresCell = document.createElement('td');
+  expiresCell.innerText = new Date(expiresAt * 1000).toLocaleString();
+
+  const actionsCell = document.createElement('td');
+  const copyInput = document.createElement('input');
+  copyInput.type = 'button';
+  copyInput.value = 'Copy Link';
+  copyInput.addEventListener('click', async _ => await navigator.clipboard.writeText(link));
+  const revokeInput = document.createElement('input');
+  revokeInput.type = 'button';
+  revokeInput.value = 'Revoke';
+  revokeInput.addEventListener('click', _ => {
+    revokeInvite(inviteId);
+    saveInvites(savedInvites().filter(invite => invite.inviteId !== inviteId));
+    row.remove();
+  });
+  actionsCell.appendChild(copyInput);
+  actionsCell.appendChild(revokeInput);
+
+  row.appendChild(teamCell);
+  row.appendChild(categoriesCell);
+  row.appendChild(expiresCell);
+  row.appendChild(actionsCell);
+
+  document.querySelector('#invites tbody').appendChild(row);
+}
+
+/**
+  * @param {string[]} categories
+  */
+function setReviewCategories(categories) {
+  const encoder = new TextEncoder();
+  let size = 5 + categories.length * 4;
+  for (const category of categories) {
+    size += encoder.encode(category).length;
+  }
+
+  const writer = new PacketWriter(size);
+  writer.writeUint8(13);
+  writer.writeStringArray(categories);
+  ws.send(writer.get());
+}
+
+/**
+  * @param {number} pendingId
+  * @param {boolean} approve
+  */
+function reviewScore(pendingId, approve) {
+  const writer = new PacketWriter(6);
+  writer.writeUint8(14);
+  writer.writeUint32(pendingId);
+  writer.writeBool(approve);
+  ws.send(writer.get());
+}
+
+/**
+  * @param {number} pendingId
+  * @param {number} refId
+  * @param {0 | 1} team
+  * @param {number} scoreId
+  * @param {boolean} undo
+  * @param {boolean} late
+  */
+function addPendingScore(pendingId, refId, team, scoreId, undo, late) {
+  const teamString = team === BLUE_ID ? 'blue' : 'red';
+
+  const row = document.createElement('tr');
+  row.classList.add(teamString);
+
+  const refCell = document.createElement('td');
+  refCell.innerText = document.querySelector(`#ref${refId} td`)?.innerText ?? `ref ${refId}`;
+
+  const teamCell = document.createElement('td');
+  teamCell.innerText = teamString;
+
+  const scoredCell = document.createElement('td');
+  scoredCell.innerText = (late ? 'LATE ' : '') + (undo ? 'UNDO ' : '') + gameInfo.scorePoints[scoreId].name;
+
+  const actionsCell = document.createElement('td');
+  const approveInput = document.createElement('input');
+  approveInput.type = 'button';
+  approveInput.value = 'Approve';
+  approveInput.addEventListener('click', _ => {
+    reviewScore(pendingId, true);
+    row.remove();
+  });
+  const rejectInput = document.createElement('input');
+  rejectInput.type = 'button';
+  rejectInput.value = 'Reject';
+  rejectInput.addEventListener('click', _ => {
+    reviewScore(pendingId, false);
+    row.remove();
+  });
+  actionsCell.appendChild(approveInput);
+  actionsCell.appendChild(rejectInput);
+
+  row.appendChild(refCell);
+  row.appendChild(teamCell);
+  row.appendChild(scoredCell);
+  row.appendChild(actionsCell);
+
+  document.querySelector('#pendingScores tbody').appendChild(row);
+}
+
+/**
+  * @param {number} seconds
+  */
+function setGracePeriod(seconds) {
+  const writer = new PacketWriter(9);
+  writer.writeUint8(20);
+  writer.writeUint64(BigInt(seconds));
+  ws.send(writer.get());
+}
+
+/**
+  * @param {number} mode
+  */
+function setConsensusMode(mode) {
+  const writer = new PacketWriter(2);
+  writer.writeUint8(15);
+  writer.writeUint8(mode);
+  ws.send(writer.get());
+}
+
+/**
+  * @param {0 | 1} team
+  * @param {number} scoreId
+  * @param {number} count
+  */
+function resolveDiscrepancy(team, scoreId, count) {
+  const writer = new PacketWriter(8);
+  writer.writeUint8(16);
+  writer.writeUint8(team);
+  writer.writeUint16(scoreId);
+  writer.writeUint32(count);
+  ws.send(writer.get());
+}
+
+/**
+  * @param {0 | 1} team
+  * @param {number} scoreId
+  * @param {boolean} disputed
+  * @param {number[]} refIds
+  * @param {number[]} counts
+  */
+function updateDiscrepancy(team, scoreId, disputed, refIds, counts) {
+  const id = `discrepancy${team}-${scoreId}`;
+  document.getElementById(id)?.remove();
+  if (!disputed) return;
+
+  const teamString = team === BLUE_ID ? 'blue' : 'red';
+  const scorePoint = gameInfo.scorePoints[scoreId];
+
+  const row = document.createElement('tr');
+  row.id = id;
+  row.classList.add(teamString);
+
+  const teamCell = document.createElement('td');
+  teamCell.innerText = teamString;
+
+  const scoredCell = document.createElement('td');
+  scoredCell.innerText = scorePoint.name;
+
+  const countsCell = document.createElement('td');
+  countsCell.innerText = refIds
+    .map((refId, i) => `${document.querySelector(`#ref${refId} td`)?.innerText ?? `ref ${refId}`}: ${counts[i]}`)
+    .join(', ');
+
+  const actionsCell = document.createElement('td');
+  const resolveInput = document.createElement('input');
+  resolveInput.type = 'button';
+  resolveInput.value = 'Resolve';
+  resolveInput.addEventListener('click', _ => {
+    const input = prompt(`Official number of ${scorePoint.name} for ${teamString}`, Math.max(...counts));
+    if (input === null) return;
+    const count = parseInt(input);
+    if (!(count >= 0)) return;
+    resolveDiscrepancy(team, scoreId, count);
+  });
+  actionsCell.appendChild(resolveInput);
+
+  row.appendChild(teamCell);
+  row.appendChild(scoredCell);
+  row.appendChild(countsCell);
+  row.appendChild(actionsCell);
+
+  document.querySelector('#discrepancies tbody').appendChild(row);
+}
+
+/**
+  * @param {boolean} locked
+  */
+function setScoringLocked(locked) {
+  const writer = new PacketWriter(2);
+  writer.writeUint8(8);
+  writer.writeBool(locked);
+  ws.send(writer.get());
+
+  scoringLocked = locked;
+}
+
+/**
+  * @param {boolean} allowed
+  */
+function setScoreWhilePaused(allowed) {
+  const writer = new PacketWriter(2);
+  writer.writeUint8(21);
+  writer.writeBool(allowed);
+  ws.send(writer.get());
+}
+
+/**
+  * @param {number} mode
+  */
+function setDisplayMode(mode) {
+  const writer = new PacketWriter(2);
+  writer.writeUint8(6);
+  writer.writeUint8(mode);
+  ws.send(writer.get());
+}
+
 function disconnect() {
+  if (outdated) document.getElementById('disconnectReason').innerText = 'This page is out of date. Please reload it.';
   document.getElementById('loadingDiv').style.display = 'none';
   document.getElementById('main').style.display = 'none';
   document.getElementById('disconnectDiv').style.display = 'block';
@@ -534,8 +1323,9 @@ function disconnect() {
   * @param {0 | 1} team
   * @param {number} scoreId
   * @param {boolean} undo
+  * @param {boolean} late
   */
-function score(team, scoreId, undo) {
+function score(team, scoreId, undo, late = false) {
   const scorePoints = gameInfo.scorePoints[scoreId];
   const points = (undo ? -1 : 1) * scorePoints.points;
   let teamString;
@@ -549,13 +1339,28 @@ function score(team, scoreId, undo) {
     teamString = 'red';
   }
 
-  addScoreLog(teamString, scorePoints, undo);
+  scoreCounts[team][scoreId] = (scoreCounts[team][scoreId] ?? 0) + (undo ? -1 : 1);
+
+  addScoreLog(teamString, scorePoints, undo, late);
 
   const scoreBeginning = undo ? `${teamString} team undo scored` : `${teamString} team scored`;
   console.log(`${scoreBeginning} (${points < 0 ? '-' : '+'}${Math.abs(points)})`);
 }
 
-function addScoreLog(team, scorePoints, undo) {
+/**
+  * @param {0 | 1} team
+  * @param {number} scoreId
+  * @param {number} count
+  * @param {boolean} late
+  */
+function applyScoreCount(team, scoreId, count, late = false) {
+  const difference = count - (scoreCounts[team][scoreId] ?? 0);
+  for (let i = 0; i < Math.abs(difference); i++) {
+    score(team, scoreId, difference < 0, late);
+  }
+}
+
+function addScoreLog(team, scorePoints, undo, late) {
   const points = (undo ? -1 : 1) * scorePoints.points;
 
   const row = document.createElement('tr');
@@ -571,7 +1376,7 @@ function addScoreLog(team, scorePoints, undo) {
   pointsCell.innerText = `${points < 0 ? '-' : '+'}${Math.abs(points)}`;
 
   const timestampCell = document.createElement('td');
-  timestampCell.innerText = formatTime(getCurrentTimeLeft());
+  timestampCell.innerText = late ? 'late' : formatTime(getCurrentTimeLeft());
 
   row.appendChild(teamCell);
   row.appendChild(scoredCell);
@@ -1,4 +1,4 @@
-import { PacketReader, PacketWriter } from "../packet.js";
+import { PacketReader, PacketWriter, PROTOCOL_VERSION, REJECT_REASONS } from "../packet.js";
 
 let rootWsUrl;
 if (location.protocol === 'https:') {
@@ -9,42 +9,209 @@ if (location.protocol === 'https:') {
 rootWsUrl += location.host;
 
 const query = new URLSearchParams(location.search);
-const team = query.get('team');
-const id = parseInt(query.get('sessionId'), 36);
-
-const ws = new WebSocket(`${rootWsUrl}/ws/join/${id}/${team}`);
+const invite = query.get('invite');
+// invite links leave these out, and they're looked up from the invite instead
+let team = query.get('team');
+let joinCode = query.get('code');
 
 const SCORES_DIV = document.getElementById('scores');
 
+const RETRY_INTERVAL = 2000;
+const MAX_RETRIES = 5;
+const RECONNECT_INTERVAL = 3000;
+/**
+  * the most offline scores the server takes in one batch
+  */
+const MAX_BATCH_LEN = 256;
+/**
+  * time between batches, since the server only takes a few packets a second
+  */
+const BATCH_INTERVAL = 250;
+
+/**
+  * a random id for this device that stays the same across reloads, so the server can tell retries apart from new scores
+  */
+// kept across tabs and reloads so the server recognises the device, e.g. to keep a banned ref out
+const clientId = localStorage.getItem('clientId') ?? [...crypto.getRandomValues(new Uint8Array(16))].map(byte => byte.toString(16).padStart(2, '0')).join('');
+localStorage.setItem('clientId', clientId);
+
+/**
+  * retry timers of scores and undos that haven't been acknowledged yet, by sequence number
+  * @type {Map<number, number>}
+  */
+const unacknowledged = new Map();
+/**
+  * scores that were sent but not acknowledged yet, by sequence number, so they can be queued again if the connection drops
+  * @type {Map<number, { scoreId: number, time: number }>}
+  */
+const sentScores = new Map();
+/**
+  * scores made while offline, sent as a batch once reconnected
+  * @type {{ seq: number, scoreId: number, time: number }[]}
+  */
+let offlineScores = [];
+
+/**
+  * @type {WebSocket}
+  */
+let ws;
+/**
+  * @type {{ name: string, role: number, pin: string }}
+  */
+let credentials;
+let offline = false;
+let reconnectTimer;
+
 let started = false;
+let paused = false;
+let scoreWhilePaused = false;
+let locked = false;
+/**
+  * the ids of the score points this ref can score, in the same order as `gameInfo.scorePoints`
+  * @type {number[]}
+  */
+let scoreIds;
+/**
+  * how many of each score point this ref has scored, in the same order as `scoreIds`
+  * @type {number[]}
+  */
+let counts;
 /**
   * @type {{ duration: number, scorePoints: { name: string, category: string, points: number }[] }}
   */
 let gameInfo;
+/**
+  * when the match started by this device's clock, used to timestamp scores made while offline
+  * @type {number?}
+  */
+let startTime = null;
 
-ws.addEventListener('open', _ => {
-  console.log('opened websocket');
-});
+document.getElementById('joinForm').addEventListener('submit', event => {
+  event.preventDefault();
 
-ws.addEventListener('close', _ => {
-  console.log('closed websocket');
+  const name = event.target.name.value.trim();
+  if (name.length == 0) return;
 
-  disconnect();
+  join(name, parseInt(event.target.role.value), event.target.pin.value.trim());
 });
 
-ws.addEventListener('error', _ => {
-  console.log('websocket errored');
-
-  disconnect();
+document.getElementById('undoBtn').addEventListener('click', _ => {
+  if (canScore()) undo(null);
 });
 
-ws.addEventListener('message', async event => {
+if (invite) {
+  const pinInput = document.getElementById('pinInput');
+  pinInput.required = false;
+  document.getElementById('pinDiv').style.display = 'none';
+}
+
+if (query.has('name') && (query.has('pin') || invite)) {
+  join(query.get('name'), parseInt(query.get('role') ?? 0), query.get('pin') ?? '');
+} else {
+  document.getElementById('nameInput').value = query.get('name') ?? '';
+  document.getElementById('joinForm').style.display = 'block';
+}
+
+/**
+  * @param {string} name
+  * @param {number} role
+  * @param {string} pin
+  */
+function join(name, role, pin) {
+  document.getElementById('joinForm').style.display = 'none';
+  document.getElementById('loadingDiv').style.display = 'block';
+
+  credentials = { name, role, pin };
+  connect();
+}
+
+async function connect() {
+  if (invite && !await findInviteMatch()) return;
+
+  const { name, role, pin } = credentials;
+  const inviteQuery = invite ? `?invite=${encodeURIComponent(invite)}` : '';
+  ws = new WebSocket(`${rootWsUrl}/ws/join/${joinCode}/${team}${inviteQuery}`);
+
+  ws.addEventListener('open', _ => {
+    console.log('opened websocket');
+
+    const encoder = new TextEncoder();
+    const writer = new PacketWriter(16 + encoder.encode(name).length + encoder.encode(pin).length + clientId.length);
+    writer.writeUint8(1);
+    writer.writeUint16(PROTOCOL_VERSION);
+    writer.writeString(name);
+    writer.writeUint8(role);
+    writer.writeString(pin);
+    writer.writeString(clientId);
+    ws.send(writer.get());
+  });
+
+  ws.addEventListener('close', event => {
+    console.log('closed websocket');
+
+    disconnect(event.reason);
+  });
+
+  ws.addEventListener('error', _ => {
+    console.log('websocket errored');
+  });
+
+  ws.addEventListener('message', onMessage);
+}
+
+/**
+  * looks up the match the invite's scoreboard is running now, returning whether there is one to join
+  */
+async function findInviteMatch() {
+  let res;
+  try {
+    res = await fetch(`/api/invite/${encodeURIComponent(invite)}`);
+  } catch {
+    if (gameInfo) {
+      goOffline();
+    } else {
+      disconnect('could not reach the server');
+    }
+    return false;
+  }
+
+  if (res.status === 404) {
+    // between matches, so wait for the host to start the next one
+    document.querySelector('#loadingDiv h1').innerText = 'Waiting for the match to be set up...';
+    clearTimeout(reconnectTimer);
+    reconnectTimer = setTimeout(connect, RECONNECT_INTERVAL);
+    return false;
+  } else if (!res.ok) {
+    disconnect(await res.text());
+    return false;
+  }
+
+  ({ join_code: joinCode, team } = await res.json());
+  return true;
+}
+
+async function onMessage(event) {
   const reader = new PacketReader(await event.data.arrayBuffer());
   switch (reader.readUint8()) {
+    // Outdated
+    case 255: {
+      console.log(`server is on protocol version ${reader.readUint16()}, this page is on ${PROTOCOL_VERSION}`);
+      disconnect('this page is out of date, please reload it');
+
+      break;
+    };
+
     // SessionInfo
     case 0: {
       console.log('session info')
       started = reader.readBool();
+      const ended = reader.readBool();
+      paused = reader.readBool();
+      scoreWhilePaused = reader.readBool();
+      const elapsed = Number(reader.readUint64());
+      locked = reader.readBool();
+      scoreIds = reader.readUint16Array();
+      counts = reader.readUint32Array();
       gameInfo = reader.readGameInfo();
 
       console.log(`started? ${started}`);
@@ -53,7 +220,31 @@ ws.addEventListener('message', async event => {
 
       init();
 
-      if (started) startGame();
+      if (started) {
+        startTime = Date.now() - elapsed;
+        startGame();
+        if (ended) endGame(null);
+      }
+      if (offline) reconnected();
+
+      break;
+    };
+
+    // ScoringLocked
+    case 3: {
+      locked = reader.readBool();
+
+      updateButtons();
+
+      break;
+    };
+
+    // Paused
+    case 8: {
+      paused = reader.readBool();
+      scoreWhilePaused = reader.readBool();
+
+      updateButtons();
 
       break;
     };
@@ -61,6 +252,7 @@ ws.addEventListener('message', async event => {
     // StartGame
     case 1: {
       started = true;
+      startTime = Date.now();
 
       startGame();
 
@@ -69,14 +261,100 @@ ws.addEventListener('message', async event => {
 
     // EndGame
     case 2: {
+      const gracePeriod = Number(reader.readUint64());
+
+      endGame(gracePeriod);
+
+      break;
+    };
+
+    // ScoreAck
+    case 5: {
+      const seq = reader.readUint32();
+      const eventId = reader.readUint32();
+      const scoreId = reader.readUint16();
+
+      acknowledge(seq);
+      sentScores.delete(seq);
+      if (document.getElementById(`event${eventId}`)) break;
+      document.getElementById(`seq${seq}`)?.remove();
+      addScoreHistory(eventId, scoreId);
+      addCount(scoreId, 1);
+
+      break;
+    };
+
+    // UndoAck
+    case 6: {
+      const seq = reader.readUint32();
+      const eventId = reader.readUint32();
+      const scoreId = reader.readUint16();
+
+      acknowledge(seq);
+      if (!document.getElementById(`event${eventId}`)?.classList.contains('undone')) addCount(scoreId, -1);
+      document.getElementById(`event${eventId}`)?.classList.add('undone');
+      document.querySelector(`#event${eventId} button`)?.remove();
+      document.getElementById('statusText').innerText = `Undid ${scorePointName(scoreId)}`;
+
+      break;
+    };
+
+    // Rejected
+    case 7: {
+      if (reader.readBool()) {
+        const seq = reader.readUint32();
+        acknowledge(seq);
+        sentScores.delete(seq);
+        document.getElementById(`seq${seq}`)?.remove();
+      }
+      const reason = REJECT_REASONS[reader.readUint8()] ?? 'unknown reason';
+      console.log(`rejected: ${reason}`);
+      document.getElementById('statusText').innerText = `Not counted: ${reason}`;
+
+      break;
+    };
+
+    // ScoreRejected
+    case 10: {
+      const eventId = reader.readUint32();
+      const scoreId = reader.readUint16();
+      const undo = reader.readBool();
+
+      const event = document.getElementById(`event${eventId}`);
+      if (undo) {
+        if (event?.classList.contains('undone')) addCount(scoreId, 1);
+        event?.classList.remove('undone');
+        if (event && !event.querySelector('button')) event.appendChild(undoButton(eventId));
+        document.getElementById('statusText').innerText = `The host did not approve undoing ${scorePointName(scoreId)}`;
+      } else {
+        if (event && !event.classList.contains('undone')) addCount(scoreId, -1);
+        event?.classList.add('undone');
+        event?.querySelector('button')?.remove();
+        document.getElementById('statusText').innerText = `The host did not approve ${scorePointName(scoreId)}`;
+      }
+
+      break;
+    };
+
+    // Malformed
+    case 9: {
+      const error = reader.readStringLength();
+      console.warn(`the server could not read a packet: ${error}`);
+      document.getElementById('statusText').innerText = `Not sent: the server could not read that (${error})`;
+
+      break;
+    };
+
+    // ScoringClosed
+    case 4: {
       started = false;
 
-      endGame();
+      closeScoring();
 
       break;
     };
   }
-});
+}
 
 function init() {
   document.getElementById('main').style.display = 'block';
@@ -85,6 +363,7 @@ function init() {
 
   document.getElementById('mainColorBar').classList.add(team);
 
+  SCORES_DIV.replaceChildren();
   for (let i = 0; i < gameInfo.scorePoints.length; i++) {
     const scorePoint = gameInfo.scorePoints[i];
     const button = document.createElement('button');
@@ -93,42 +372,259 @@ function init() {
     button.classList.add('score');
     button.innerText = scorePoint.name;
     button.addEventListener('click', () => {
-      if (started) score(i);
+      if (canScore()) score(scoreIds[i]);
     });
 
     const subtext = document.createElement('div');
     subtext.classList.add('subtext');
     subtext.innerText = `${points < 0 ? '-' : '+'}${Math.abs(points)}`;
 
+    const count = document.createElement('div');
+    count.classList.add('count');
+    count.innerText = `scored ${counts[i]}`;
+
     button.appendChild(subtext);
+    button.appendChild(count);
 
     SCORES_DIV.appendChild(button);
   }
 }
 
 function startGame() {
-  for (const scoreButton of SCORES_DIV.children) {
-    scoreButton.disabled = false;
-  }
+  updateButtons();
+}
+
+/**
+  * @param {number?} gracePeriod how long late scores are accepted for, or `null` if only some of it is left
+  */
+function endGame(gracePeriod) {
+  document.getElementById('lateText').innerText = gracePeriod === null
+    ? 'The match is over. Late scores are still accepted for a few seconds.'
+    : `The match is over. Late scores are accepted for ${gracePeriod} seconds.`;
+  document.getElementById('lateText').style.display = 'block';
 }
 
-function endGame() {
+/**
+  * @param {number} scoreId
+  * @param {number} change
+  */
+function addCount(scoreId, change) {
+  const index = scoreIds.indexOf(scoreId);
+  if (index == -1) return;
+  counts[index] += change;
+  SCORES_DIV.children[index].querySelector('.count').innerText = `scored ${counts[index]}`;
+}
+
+function closeScoring() {
+  document.getElementById('lateText').style.display = 'none';
+  document.getElementById('closedText').style.display = 'block';
+  updateButtons();
+}
+
+function updateButtons() {
   for (const scoreButton of SCORES_DIV.children) {
-    scoreButton.disabled = true;
+    scoreButton.disabled = !canScore();
   }
+  for (const undoButton of document.querySelectorAll('#undoBtn, #scoreHistory button')) {
+    undoButton.disabled = !canScore();
+  }
+  document.getElementById('lockedText').style.display = locked ? 'block' : 'none';
+  document.getElementById('pausedText').style.display = paused ? 'block' : 'none';
+  document.getElementById('pausedText').innerText = scoreWhilePaused ? 'The match is paused, but scoring is still allowed.' : 'The match is paused.';
+}
+
+function canScore() {
+  return started && !locked && (!paused || scoreWhilePaused);
 }
 
-function disconnect() {
+/**
+  * @param {string?} reason
+  */
+function disconnect(reason) {
+  // a connection that dropped on its own after joining is retried, keeping scores made in the meantime
+  if (!reason && gameInfo) {
+    goOffline();
+    return;
+  }
+
   document.getElementById('main').style.display = 'none';
   document.getElementById('loadingDiv').style.display = 'none';
   document.getElementById('disconnectDiv').style.display = 'block';
+  if (reason) document.getElementById('disconnectReason').innerText = `Reason: ${reason}`;
+}
+
+function goOffline() {
+  if (!offline) {
+    offline = true;
+    document.getElementById('offlineText').style.display = 'block';
+
+    for (const [scoreSeq, { scoreId, time }] of sentScores) {
+      acknowledge(scoreSeq);
+      offlineScores.push({ seq: scoreSeq, scoreId, time });
+      setPendingText(scoreSeq, '(offline)');
+    }
+    sentScores.clear();
+  }
+
+  clearTimeout(reconnectTimer);
+  reconnectTimer = setTimeout(connect, RECONNECT_INTERVAL);
+}
+
+function reconnected() {
+  offline = false;
+  document.getElementById('offlineText').style.display = 'none';
+  if (offlineScores.length == 0) return;
+
+  for (let start = 0; start < offlineScores.length; start += MAX_BATCH_LEN) {
+    const batch = offlineScores.slice(start, start + MAX_BATCH_LEN);
+    for (const { seq, scoreId, time } of batch) {
+      sentScores.set(seq, { scoreId, time });
+      setPendingText(seq, '(sending)');
+    }
+    setTimeout(() => sendBatch(batch), start / MAX_BATCH_LEN * BATCH_INTERVAL);
+  }
+  offlineScores = [];
+}
+
+/**
+  * @param {{ seq: number, scoreId: number, time: number }[]} batch
+  */
+function sendBatch(batch) {
+  // scores that weren't sent before the connection dropped are queued again by `goOffline`
+  if (offline || ws.readyState !== WebSocket.OPEN) return;
+
+  const writer = new PacketWriter(5 + 14 * batch.length);
+  writer.writeUint8(3);
+  writer.writeUint32(batch.length);
+  for (const { seq, scoreId, time } of batch) {
+    writer.writeUint32(seq);
+    writer.writeUint16(scoreId);
+    writer.writeUint64(BigInt(time));
+  }
+  ws.send(writer.get());
+}
+
+/**
+  * @param {number} scoreSeq
+  * @param {string} text
+  */
+function setPendingText(scoreSeq, text) {
+  const li = document.getElementById(`seq${scoreSeq}`);
+  if (li) li.innerText = li.innerText.replace(/\(.*\)$/, text);
 }
 
 function score(id) {
-  const writer = new PacketWriter(3);
+  const scoreSeq = nextSeq();
+  const time = Date.now() - (startTime ?? Date.now());
+
+  const li = document.createElement('li');
+  li.id = `seq${scoreSeq}`;
+  li.classList.add('pending');
+  li.innerText = `${scorePointName(id)} (sending)`;
+  document.getElementById('scoreHistory').prepend(li);
+
+  if (offline || ws.readyState !== WebSocket.OPEN) {
+    offlineScores.push({ seq: scoreSeq, scoreId: id, time });
+    setPendingText(scoreSeq, '(offline)');
+    return;
+  }
+
+  sentScores.set(scoreSeq, { scoreId: id, time });
+  const writer = new PacketWriter(7);
   writer.writeUint8(0);
-  writer.writeUint8(id);
-  writer.writeBool(document.getElementById('undoCheckbox').checked);
-  ws.send(writer.get());
+  writer.writeUint32(scoreSeq);
+  writer.writeUint16(id);
+  sendUntilAcknowledged(scoreSeq, writer.get());
+}
+
+/**
+  * @param {number?} eventId the score to undo, or `null` for the latest one
+  */
+function undo(eventId) {
+  if (offline || ws.readyState !== WebSocket.OPEN) {
+    document.getElementById('statusText').innerText = 'Scores cannot be undone while offline';
+    return;
+  }
+
+  const undoSeq = nextSeq();
+
+  const writer = new PacketWriter(eventId === null ? 6 : 10);
+  writer.writeUint8(2);
+  writer.writeUint32(undoSeq);
+  writer.writeBool(eventId !== null);
+  if (eventId !== null) writer.writeUint32(eventId);
+  sendUntilAcknowledged(undoSeq, writer.get());
+}
+
+function nextSeq() {
+  // every tab shares the client id, so they share the sequence too
+  const seq = parseInt(localStorage.getItem('seq') ?? '0') + 1;
+  localStorage.setItem('seq', seq);
+  return seq;
+}
+
+/**
+  * sends a packet, resending it with the same sequence number until the server acknowledges it
+  * @param {number} packetSeq
+  * @param {ArrayBuffer} bytes
+  */
+function sendUntilAcknowledged(packetSeq, bytes) {
+  ws.send(bytes);
+
+  let retries = 0;
+  const timer = setInterval(() => {
+    // scores still waiting when the connection drops are queued again by `goOffline`
+    if (offline) return;
+    if (retries++ >= MAX_RETRIES || ws.readyState !== WebSocket.OPEN) {
+      sentScores.delete(packetSeq);
+      acknowledge(packetSeq);
+      const li = document.getElementById(`seq${packetSeq}`);
+      li?.classList.add('failed');
+      setPendingText(packetSeq, '(not sent)');
+      return;
+    }
+    ws.send(bytes);
+  }, RETRY_INTERVAL);
+  unacknowledged.set(packetSeq, timer);
+}
+
+/**
+  * @param {number} packetSeq
+  */
+function acknowledge(packetSeq) {
+  clearInterval(unacknowledged.get(packetSeq));
+  unacknowledged.delete(packetSeq);
+}
+
+/**
+  * @param {number} eventId
+  * @param {number} scoreId
+  */
+function addScoreHistory(eventId, scoreId) {
+  const li = document.createElement('li');
+  li.id = `event${eventId}`;
+  li.innerText = `${scorePointName(scoreId)} `;
+  li.appendChild(undoButton(eventId));
+
+  document.getElementById('scoreHistory').prepend(li);
+}
+
+/**
+  * @param {number} eventId
+  */
+function undoButton(eventId) {
+  const button = document.createElement('button');
+  button.innerText = 'Undo';
+  button.addEventListener('click', _ => {
+    if (canScore()) undo(eventId);
+  });
+  return button;
+}
+
+/**
+  * @param {number} scoreId
+  */
+function scorePointName(scoreId) {
+  return gameInfo.scorePoints[scoreIds.indexOf(scoreId)]?.name ?? 'score';
 }
 
@@ -0,0 +1,285 @@
+// served at /overlay/{join code}/{layout}
+const [code, layoutName] = location.pathname.split('/').slice(2);
+
+const LAYOUTS = {
+  score_bar: 'scoreBar',
+  results: 'results',
+  timer: 'timer',
+};
+
+const layout = LAYOUTS[layoutName] ?? LAYOUTS.score_bar;
+
+/**
+  * the version of the packet format, which must match the server's; keep in sync with `packet.js`
+  */
+const PROTOCOL_VERSION = 10;
+
+const eventSource = new EventSource(`/sse/view/${code}`);
+
+let startedTime;
+let gameEnded = false;
+
+let timePaused = 0;
+let gamePaused = false;
+/**
+  * @type {{ name: string, category: string, points: number }[]}
+  */
+let scorePoints;
+
+const points = {
+  blue: {
+    total: 0,
+    categories: { }
+  },
+  red: {
+    total: 0,
+    categories: { }
+  },
+};
+
+/**
+  * official number of times each score point was scored
+  * @type {{ blue: { [scoreId: number]: number }, red: { [scoreId: number]: number } }}
+  */
+const scoreCounts = { blue: {}, red: {} };
+
+eventSource.addEventListener('message', event => {
+  /**
+    * @type {{ type: 'session_info' | 'score' | 'score_set' | 'game_start' | 'game_end' | 'reveal_score' | 'game_pause' | 'game_unpause', content: any }}
+    */
+  const data = JSON.parse(event.data);
+
+  if (data.type === 'session_info') {
+    // the server was updated since this page loaded
+    if (data.content.version !== PROTOCOL_VERSION) {
+      eventSource.close();
+      location.reload();
+      return;
+    }
+    init(data.content.blue_teams, data.content.red_teams, data.content.match_number, data.content.data, data.content.state);
+  } else if (data.type === 'score') {
+    score(data.content);
+    if (!gameEnded) {
+      updatePoints();
+    }
+  } else if (data.type === 'score_set') {
+    setScore(data.content);
+    if (!gameEnded) {
+      updatePoints();
+    }
+  } else if (data.type === 'game_start') {
+    startedTime = data.content.time_started;
+  } else if (data.type === 'game_end') {
+    gameEnded = true;
+  } else if (data.type === 'reveal_score') {
+    startedTime = null;
+    updatePoints();
+    showResults();
+  } else if (data.type === 'game_pause') {
+    gamePaused = true;
+  } else if (data.type === 'game_unpause') {
+    gamePaused = false;
+    timePaused += data.content.paused_time;
+  }
+});
+
+/**
+  * @param {string[]} blueTeams
+  * @param {string[]} redTeams
+  * @param {number} matchNumber
+  * @param {{ duration: number, score_points: { name: string, category: string, points: number }[] }} data
+  * @param {{
+    blue_scored: { [key: number]: { scored: number, undo: number } },
+    red_scored: { [key: number]: { scored: number, undo: number } },
+    time_started: number?,
+    time_paused: number,
+    paused: boolean,
+    ended: boolean
+  }} state
+  */
+function init(blueTeams, redTeams, matchNumber, data, state) {
+  startedTime = state.time_started;
+  scorePoints = data.score_points;
+  gamePaused = state.paused;
+  timePaused = state.time_paused;
+  gameEnded = state.ended;
+
+  points.blue = getScored(state.blue_scored);
+  points.red = getScored(state.red_scored);
+  for (const [scoreId, timesScored] of Object.entries(state.blue_scored)) {
+    scoreCounts.blue[scoreId] = timesScored.scored - timesScored.undo;
+  }
+  for (const [scoreId, timesScored] of Object.entries(state.red_scored)) {
+    scoreCounts.red[scoreId] = timesScored.scored - timesScored.undo;
+  }
+
+  document.getElementById('scoreBarBlueTeams').innerText = blueTeams.join(' ');
+  document.getElementById('scoreBarRedTeams').innerText = redTeams.join(' ');
+  document.getElementById('scoreBarMatch').innerText = `Match ${matchNumber}`;
+  document.getElementById('resultsMatch').innerText = `Match ${matchNumber} Results`;
+  generateTeamList('blue', blueTeams);
+  generateTeamList('red', redTeams);
+
+  if (layout !== LAYOUTS.results) {
+    document.getElementById(layout).classList.add('active');
+  }
+
+  startUpdateTimeInterval(data.duration);
+
+  updatePoints();
+
+  if (gameEnded && !startedTime) showResults();
+}
+
+function startUpdateTimeInterval(duration) {
+  const timeTexts = [document.getElementById('scoreBarTime'), document.getElementById('timerTime')];
+  setInterval(() => {
+    if (gamePaused) return;
+
+    const text = formatTime(getTimeLeft(duration));
+    for (const timeText of timeTexts) {
+      if (timeText.innerText !== text) timeText.innerText = text;
+    }
+  }, 100);
+}
+
+function getTimeLeft(duration) {
+  if (gameEnded) {
+    return 0;
+  } else if (startedTime) {
+    const timeLeft = duration * 1000 - (Date.now() - (startedTime + timePaused));
+    return Math.max(0, timeLeft);
+  } else {
+    return duration * 1000;
+  }
+}
+
+function showResults() {
+  const blue = document.getElementById('resultsBlue');
+  const red = document.getElementById('resultsRed');
+  if (points.blue.total > points.red.total) {
+    blue.classList.add('winner');
+  } else if (points.red.total > points.blue.total) {
+    red.classList.add('winner');
+  } else {
+    blue.classList.add('tied');
+    red.classList.add('tied');
+  }
+
+  generateCategoryTable('blue');
+  generateCategoryTable('red');
+
+  if (layout === LAYOUTS.results) {
+    document.getElementById(layout).classList.add('active');
+  }
+}
+
+/**
+  * @param {'blue' | 'red'} team
+  * @param {string[]} names
+  */
+function generateTeamList(team, names) {
+  const teams = document.getElementById(`results${capitalize(team)}Teams`);
+  for (const name of names) {
+    const li = document.createElement('li');
+    li.innerText = name;
+    teams.appendChild(li);
+  }
+}
+
+/**
+  * @param {'blue' | 'red'} team
+  */
+function generateCategoryTable(team) {
+  const table = document.getElementById(`results${capitalize(team)}Categories`);
+  const categories = [];
+  table.replaceChildren();
+
+  for (const { category } of scorePoints) {
+    if (categories.includes(category)) continue;
+    categories.push(category);
+
+    const row = table.insertRow();
+    row.insertCell().innerText = category;
+    row.insertCell().innerText = points[team].categories[category] ?? 0;
+  }
+}
+
+/**
+  * @param {{ team: 'blue' | 'red', score_id: number, undo: boolean }} content
+  */
+function score(content) {
+  scoreCounts[content.team][content.score_id] = (scoreCounts[content.team][content.score_id] ?? 0) + (content.undo ? -1 : 1);
+
+  const scored = scorePoints[content.score_id];
+  const pointsScored = (content.undo ? -1 : 1) * scored.points;
+  const teamPoints = points[content.team];
+
+  teamPoints.total += pointsScored;
+  teamPoints.categories[scored.category] ??= 0;
+  teamPoints.categories[scored.category] += pointsScored;
+}
+
+/**
+  * @param {{ team: 'blue' | 'red', score_id: number, count: number }} content
+  */
+function setScore(content) {
+  scoreCounts[content.team][content.score_id] = content.count;
+  // recounted from every score point at once, however far the count jumped
+  const scored = Object.fromEntries(Object.entries(scoreCounts[content.team]).map(([scoreId, count]) => [scoreId, { scored: count, undo: 0 }]));
+  points[content.team] = getScored(scored);
+}
+
+function updatePoints() {
+  document.getElementById('scoreBarBluePoints').innerText = points.blue.total;
+  document.getElementById('scoreBarRedPoints').innerText = points.red.total;
+  document.getElementById('resultsBluePoints').innerText = points.blue.total;
+  document.getElementById('resultsRedPoints').innerText = points.red.total;
+}
+
+/**
+  * @param {{ [key: number]: { scored: number, undo: number } }} scored
+  */
+function getScored(scored) {
+  const allianceScored = {
+    total: 0,
+    categories: {},
+  };
+  for (const [scoreId, timesScored] of Object.entries(scored)) {
+    const score = scorePoints[parseInt(scoreId)];
+    const awardedPoints = score.points * (timesScored.scored - timesScored.undo);
+    allianceScored.total += awardedPoints;
+    allianceScored.categories[score.category] ??= 0;
+    allianceScored.categories[score.category] += awardedPoints;
+  }
+  return allianceScored;
+}
+
+/**
+  * @param {string} str
+  */
+function capitalize(str) {
+  return str.charAt(0).toUpperCase() + str.slice(1);
+}
+
+function formatTime(time) {
+  const minutes = Math.floor(time / 1000 / 60);
+  const seconds = Math.floor(time / 1000 % 60);
+
+  return `${minutes}:${leftPad(seconds.toString(), '0', 2)}`
+}
+
+/**
+  * @param {string} str
+  * @param {string} padString
+  * @param {number} size
+  */
+function leftPad(str, padString, size) {
+  let padded = '';
+
+  while (padded.length + str.length < size) {
+    padded += padString;
+  }
+
+  return padded + str;
+}
@@ -1,3 +1,30 @@
+/**
+  * the version of the packet format, which must match the server's; bump it whenever a packet changes
+  */
+export const PROTOCOL_VERSION = 10;
+
+/**
+  * why the server rejected a packet, by the reason's id
+  */
+export const REJECT_REASONS = [
+  'the server could not read that',
+  'the match has not started',
+  'that score does not exist',
+  'scoring is locked by the host',
+  'you are scoring too fast',
+  'you are not allowed to score that',
+  'scoring is closed',
+  'there is nothing to undo',
+  'that score was made by another ref',
+  'that score was already undone',
+  'that score was already taken off',
+  'that score was not made during the match',
+  'the match is paused',
+  'scores are waiting for review',
+  'the refs disagree on the score',
+  'too many offline scores were sent at once',
+];
+
 export class PacketReader {
   #index = 0;
   /**
@@ -37,18 +64,47 @@ export class PacketReader {
   }
 
   readStringLength() {
-    const len = this.readUint64();
-    const utf8 = [];
+    const len = this.readUint32();
+    const utf8 = new Uint8Array(this.#buf.buffer, this.#buf.byteOffset + this.#next(len), len);
+
+    return new TextDecoder().decode(utf8);
+  }
+
+  readUint8Array() {
+    const len = this.readUint32();
+    const array = [];
 
     for (let i = 0; i < len; i++) {
-      utf8.push(this.readUint8());
+      array[i] = this.readUint8();
     }
 
-    return String.fromCharCode(...utf8);
+    return array;
+  }
+
+  readUint16Array() {
+    const len = this.readUint32();
+    const array = [];
+
+    for (let i = 0; i < len; i++) {
+      array[i] = this.readUint16();
+    }
+
+    return array;
+  }
+
+  readUint32Array() {
+    const len = this.readUint32();
+    const array = [];
+
+    for (let i = 0; i < len; i++) {
+      array[i] = this.readUint32();
+    }
+
+    return array;
   }
 
   readStringArray() {
-    const len = this.readUint64();
+    const len = this.readUint32();
     const array = [];
 
     for (let i = 0; i < len; i++) {
@@ -66,8 +122,9 @@ export class PacketReader {
     const scores = [];
 
     gameInfo.duration = this.readUint16();
+    const len = this.readUint32();
 
-    while (!this.atEnd()) {
+    for (let i = 0; i < len; i++) {
       scores.push({ name: this.readStringLength(), category: this.readStringLength(), points: this.readInt8() });
     }
 
@@ -77,7 +134,7 @@ export class PacketReader {
   }
 
   atEnd() {
-    return this.#index + 1 >= this.#buf.byteLength;
+    return this.#index >= this.#buf.byteLength;
   }
 
   #next(amount) {
@@ -117,6 +174,11 @@ export class PacketWriter {
     this.#index += 2;
   }
 
+  writeUint32(data) {
+    this.#buf.setUint32(this.#index, data, true);
+    this.#index += 4;
+  }
+
   writeUint64(data) {
     this.#buf.setBigUint64(this.#index, data, true);
     this.#index += 8;
@@ -127,7 +189,7 @@ export class PacketWriter {
     */
   writeString(data) {
     const encoded = new TextEncoder().encode(data);
-    this.writeUint64(BigInt(encoded.length));
+    this.writeUint32(encoded.length);
     for (const utf8 of encoded) {
       this.writeUint8(utf8);
     }
@@ -137,7 +199,7 @@ export class PacketWriter {
    * @param {string[]} data
    */
   writeStringArray(data) {
-    this.writeUint64(BigInt(data.length));
+    this.writeUint32(data.length);
     for (const string of data) {
       this.writeString(string);
     }
@@ -148,6 +210,7 @@ export class PacketWriter {
     */
   writeGameData(data) {
     this.writeUint16(data.duration);
+    this.writeUint32(data.scorePoints.length);
 
     for (const scorePoint of data.scorePoints) {
       this.writeString(scorePoint.name);
@@ -1,12 +1,19 @@
 const query = new URLSearchParams(window.location.search);
 
-const eventSource = new EventSource(`/sse/view/${parseInt(query.get('id'), 36)}`);
+/**
+  * the version of the packet format, which must match the server's; keep in sync with `packet.js`
+  */
+const PROTOCOL_VERSION = 10;
+
+const eventSource = new EventSource(`/sse/view/${query.get('code')}`);
 
 let startedTime;
 let gameEnded = false;
 
 let timePaused = 0;
 let gamePaused = false;
+
+let loaded = false;
 /**
   * @type {{ name: string, category: string, points: number }[]}
   */
@@ -23,31 +30,52 @@ const points = {
   },
 };
 
+/**
+  * official number of times each score point was scored
+  * @type {{ blue: { [scoreId: number]: number }, red: { [scoreId: number]: number } }}
+  */
+const scoreCounts = { blue: {}, red: {} };
+
 eventSource.addEventListener('message', event => {
   /**
-    * @type {{ type: 'session_info' | 'score' | 'game_start' | 'game_end' | 'reveal_score' | 'game_pause' | 'game_unpause', content: any }}
+    * @type {{ type: 'session_info' | 'score' | 'score_set' | 'game_start' | 'game_end' | 'reveal_score' | 'game_pause' | 'game_unpause' | 'display_mode', content: any }}
     */
   const data = JSON.parse(event.data);
 
   if (data.type === 'session_info') {
+    // the server was updated since this page loaded
+    if (data.content.version !== PROTOCOL_VERSION) {
+      eventSource.close();
+      location.reload();
+      return;
+    }
     init(data.content.blue_teams, data.content.red_teams, data.content.match_number, data.content.data, data.content.state);
+    setDisplayMode(data.content.display_mode);
   } else if (data.type === 'score') {
     score(data.content);
     if (!gameEnded) {
       updatePoints();
     }
+  } else if (data.type === 'score_set') {
+    setScore(data.content);
+    if (!gameEnded) {
+      updatePoints();
+    }
   } else if (data.type === 'game_start') {
     startedTime = data.content.time_started;
   } else if (data.type === 'game_end') {
     gameEnded = true;
   } else if (data.type === 'reveal_score') {
     startedTime = null;
+    document.body.dataset.revealed = '';
     updatePoints();
   } else if (data.type === 'game_pause') {
     gamePaused = true;
   } else if (data.type === 'game_unpause') {
     gamePaused = false;
     timePaused += data.content.paused_time;
+  } else if (data.type === 'display_mode') {
+    setDisplayMode(data.content.mode);
   }
 });
 
@@ -74,12 +102,19 @@ function init(blueTeams, redTeams, matchNumber, data, state) {
   gamePaused = state.paused;
   timePaused = state.time_paused;
   gameEnded = state.ended;
+  if (state.revealed) document.body.dataset.revealed = '';
 
   document.getElementById('loading').style.display = 'none';
-  document.getElementById('main').style.display = 'flex';
+  loaded = true;
 
   points.blue = getScored(state.blue_scored);
   points.red = getScored(state.red_scored);
+  for (const [scoreId, timesScored] of Object.entries(state.blue_scored)) {
+    scoreCounts.blue[scoreId] = timesScored.scored - timesScored.undo;
+  }
+  for (const [scoreId, timesScored] of Object.entries(state.red_scored)) {
+    scoreCounts.red[scoreId] = timesScored.scored - timesScored.undo;
+  }
 
   generateScoreCategories();
   generateTeamList('blue', blueTeams);
@@ -91,6 +126,38 @@ function init(blueTeams, redTeams, matchNumber, data, state) {
   updatePoints();
 }
 
+const slideImage = document.getElementById('slideImage');
+// without an image for the slide, its title is shown on its own
+slideImage.addEventListener('error', _ => slideImage.style.display = 'none');
+
+const SLIDES = {
+  rankings: 'Rankings',
+  sponsor_slide: 'Thank You To Our Sponsors',
+};
+
+/**
+  * @param {'blank' | 'match_preview' | 'live' | 'results' | 'rankings' | 'sponsor_slide'} mode
+  */
+function setDisplayMode(mode) {
+  document.body.dataset.mode = mode;
+  if (!loaded) return;
+
+  const main = document.getElementById('main');
+  const slide = document.getElementById('slide');
+
+  main.style.display = 'none';
+  slide.style.display = 'none';
+
+  if (mode in SLIDES) {
+    document.getElementById('slideTitle').innerText = SLIDES[mode];
+    slideImage.style.display = '';
+    slideImage.src = `slides/${mode}.png`;
+    slide.style.display = 'flex';
+  } else if (mode !== 'blank') {
+    main.style.display = 'flex';
+  }
+}
+
 function startUpdateTimeInterval(duration) {
   const timeLeftText = document.getElementById('timeLeftText');
   const id = setInterval(() => {
@@ -180,6 +247,8 @@ function generateTeamList(team, names) {
   * @param {{ team: 'blue' | 'red', score_id: number, undo: boolean }} content 
   */
 function score(content) {
+  scoreCounts[content.team][content.score_id] = (scoreCounts[content.team][content.score_id] ?? 0) + (content.undo ? -1 : 1);
+
   const scored = scorePoints[content.score_id];
   const pointsScored = (content.undo ? -1 : 1) * scored.points;
   let teamPoints;
@@ -198,6 +267,16 @@ function score(content) {
   }
 }
 
+/**
+  * @param {{ team: 'blue' | 'red', score_id: number, count: number }} content
+  */
+function setScore(content) {
+  scoreCounts[content.team][content.score_id] = content.count;
+  // recounted from every score point at once, however far the count jumped
+  const scored = Object.fromEntries(Object.entries(scoreCounts[content.team]).map(([scoreId, count]) => [scoreId, { scored: count, undo: 0 }]));
+  points[content.team] = getScored(scored);
+}
+
 function updatePoints() {
   document.getElementById('bluePoints').innerText = points.blue.total;
   document.getElementById('redPoints').innerText = points.red.total;
@@ -4,49 +4,90 @@ use axum::{response::IntoResponse, Json};
 use lazy_static::lazy_static;
 use serde::Serialize;
 
-use crate::packet::{Writable, Readable, PacketReader, PacketWriter};
+use crate::{packet::{DecodeError, Writable, Readable, PacketReader, PacketWriter}, session_manager::Team};
 
-#[derive(Clone, Debug, Serialize)]
+/// The most score points a game can have. The score point list itself is length-prefixed like any other list,
+/// but every packet refers to a score point by its index as a `u16` id, so any more could never be scored.
+/// Games sent by hosts are also held to the `MAX_LIST_LEN` decode limit, which is far lower by default.
+pub const MAX_SCORE_POINTS: usize = u16::MAX as usize + 1;
+
+/// The highest count the host can set a score point to. Far more than any match scores, but low enough that
+/// ref scores on top of it can't overflow a count.
+pub const MAX_SCORE_COUNT: u32 = 9999;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct GameData {
     pub duration: GameDuration,
     pub score_points: Box<[ScorePoint]>,
 }
 
 impl Readable for GameData {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
         let duration = reader.read()?;
 
-        let mut score_points = Vec::new();
-        while reader.has_next() {
-            if score_points.len() == 256 { break; }
-            let score_point = reader.read()?;
-            score_points.push(score_point);
+        let offset = reader.offset();
+        let score_points: Vec<ScorePoint> = reader.read()?;
+        if score_points.len() > MAX_SCORE_POINTS {
+            return Err(DecodeError::LimitExceeded { what: "score point list", len: score_points.len(), max: MAX_SCORE_POINTS, offset });
         }
 
-        if score_points.len() == 256 { return None; }
-        Some(GameData { duration, score_points: score_points.into() })
+        Ok(GameData { duration, score_points: score_points.into() })
     }
 }
 
 impl Writable for GameData {
     fn write(self, writer: &mut PacketWriter) {
-        writer.write(self.duration.secs);
-        for ScorePoint { name, category, points } in Vec::from(self.score_points) {
-            writer.write(name);
-            writer.write(category);
-            writer.write(points);
-        }
+        writer.write(self.duration);
+        writer.write(Vec::from(self.score_points));
     }
 }
 
 #[derive(Clone, Debug, Default, Serialize)]
 pub struct GameState {
-    pub blue_scored: HashMap<u8, ScoredRecord>,
-    pub red_scored: HashMap<u8, ScoredRecord>,
+    pub blue_scored: HashMap<u16, ScoredRecord>,
+    pub red_scored: HashMap<u16, ScoredRecord>,
     pub time_started: Option<u64>,
     pub time_paused: u64,
     pub paused: bool,
     pub ended: bool,
+    pub revealed: bool,
+    /// When the game was paused and unpaused, in milliseconds since it started by the server's clock.
+    #[serde(skip)]
+    pub pauses: Vec<(u64, Option<u64>)>,
+    /// When the game ended, in milliseconds since it started by the server's clock.
+    #[serde(skip)]
+    pub time_ended: Option<u64>,
+}
+
+impl GameState {
+    pub fn apply_score(&mut self, team: Team, score_id: u16, undo: bool) {
+        let scored = match team {
+            Team::Red => &mut self.red_scored,
+            Team::Blue => &mut self.blue_scored,
+        };
+        let scores = scored.entry(score_id).or_default();
+        if undo {
+            scores.undo += 1;
+        } else {
+            scores.scored += 1;
+        }
+    }
+
+    pub fn set_score(&mut self, team: Team, score_id: u16, count: u32) {
+        let scored = match team {
+            Team::Red => &mut self.red_scored,
+            Team::Blue => &mut self.blue_scored,
+        };
+        *scored.entry(score_id).or_default() = ScoredRecord { scored: count as i32, undo: 0 };
+    }
+
+    pub fn count(&self, team: Team, score_id: u16) -> i32 {
+        let scored = match team {
+            Team::Red => &self.red_scored,
+            Team::Blue => &self.blue_scored,
+        };
+        scored.get(&score_id).map(|scores| scores.scored - scores.undo).unwrap_or_default()
+    }
 }
 
 #[derive(Clone, Debug, Default, Serialize)]
@@ -55,7 +96,7 @@ pub struct ScoredRecord {
     pub undo: i32,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct ScorePoint {
     pub name: String,
     pub category: String,
@@ -63,23 +104,37 @@ pub struct ScorePoint {
 }
 
 impl Readable for ScorePoint {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
         let name = reader.read()?;
         let category = reader.read()?;
         let points = reader.read()?;
 
-        Some(ScorePoint { name, category, points })
+        Ok(ScorePoint { name, category, points })
     }
 }
 
-#[derive(Clone, Debug)]
+impl Writable for ScorePoint {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write(self.name);
+        writer.write(self.category);
+        writer.write(self.points);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct GameDuration {
     pub secs: u16,
 }
 
 impl Readable for GameDuration {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
-        Some(GameDuration { secs: reader.read()? })
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        Ok(GameDuration { secs: reader.read()? })
+    }
+}
+
+impl Writable for GameDuration {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write(self.secs);
     }
 }
 
@@ -104,8 +159,10 @@ pub struct BuiltinGame {
 }
 
 impl Readable for &'static BuiltinGame {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
-        BUILTIN.games.get(reader.read::<usize>()?)
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let offset = reader.offset();
+        let index = reader.read::<u32>()?;
+        BUILTIN.games.get(index as usize).ok_or(DecodeError::InvalidVariant { name: "builtin game", variant: index, offset })
     }
 }
 
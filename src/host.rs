@@ -1,45 +1,78 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use axum::{extract::{State, WebSocketUpgrade, ws::{Message, WebSocket}}, response::{IntoResponse, Response}};
+use axum::{extract::{State, WebSocketUpgrade, ws::{close_code, CloseFrame, Message, WebSocket}}, response::{IntoResponse, Response}};
 use futures::{StreamExt, SinkExt};
-use rand::{thread_rng, Rng};
-use tokio::{sync::{broadcast::error::RecvError, mpsc}, time::{MissedTickBehavior, timeout}};
+use tokio::{sync::{broadcast::{self, error::RecvError}, mpsc::{self, error::SendError, UnboundedSender}}, time::{MissedTickBehavior, timeout}};
 use tracing::{error, info};
 
-use crate::{AppState, game::GameData, packet::{ClientboundHostPacket, Either, FromBytes, IntoBytes, ServerboundHostPacket}, session_manager::{HostMessage, Session, Team, UserMessage, ViewerMessage}};
+use crate::{AppState, game::{GameData, MAX_SCORE_COUNT}, invite::{self, Invite, ScoreboardId}, packet::{ClientboundHostPacket, DecodeLimits, Either, FromBytes, IntoBytes, OUTDATED_REASON, PROTOCOL_VERSION, ServerboundHostPacket}, session_manager::{HostMessage, MAX_GRACE_PERIOD, RefInfo, RefScore, RejectReason, ScoreChange, ScoreCounts, Session, Team, UserMessage, ViewerMessage}};
 
-pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
-    let session_id = thread_rng().gen();
+const MAX_REASON_LEN: usize = 200;
+const MAX_SCOREBOARD_KEY_LEN: usize = 64;
 
-    ws.on_upgrade(move |ws| handle_socket(ws, session_id, state)).into_response()
+pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    ws.max_message_size(DecodeLimits::current().max_packet_len)
+        .on_upgrade(move |ws| handle_socket(ws, state))
+        .into_response()
 }
 
-async fn handle_socket(mut ws: WebSocket, session_id: u32, state: AppState) {
+async fn handle_socket(mut ws: WebSocket, state: AppState) {
     if let Ok(Some(Ok(Message::Binary(bytes)))) = timeout(Duration::from_secs(3), async { ws.recv().await }).await {
-        if let Some(ServerboundHostPacket::GameData { match_number, blue_teams, red_teams, game_type }) = ServerboundHostPacket::from_bytes(bytes) {
-            let game_data = match game_type {
-                Either::Left(builtin) => builtin.data.clone(),
-                Either::Right(custom) => custom,
-            };
-            session_start(ws, session_id, blue_teams, red_teams, match_number, game_data, state).await;
+        match ServerboundHostPacket::from_bytes(bytes) {
+            Ok(ServerboundHostPacket::GameData { version: PROTOCOL_VERSION, scoreboard_key, match_number, blue_teams, red_teams, game_type }) => {
+                if scoreboard_key.is_empty() || scoreboard_key.len() > MAX_SCOREBOARD_KEY_LEN {
+                    let _ = ws.close().await;
+                    return;
+                }
+                let game_data = match game_type {
+                    Either::Left(builtin) => builtin.data.clone(),
+                    Either::Right(custom) => custom,
+                };
+                session_start(ws, ScoreboardId::from_key(&scoreboard_key), blue_teams, red_teams, match_number, game_data, state).await;
+            },
+            // a first packet that can't be read most likely came from an old cached page
+            Ok(ServerboundHostPacket::GameData { version, .. }) => {
+                info!("host is outdated (version {version})");
+                outdated(ws).await;
+            },
+            Err(err) => {
+                info!("host is outdated ({err})");
+                outdated(ws).await;
+            },
+            Ok(_) => { let _ = ws.close().await; },
         }
     } else { ws.close().await.expect("can close ws"); };
 }
 
-async fn session_start(mut ws: WebSocket, session_id: u32, blue_teams: Vec<String>, red_teams: Vec<String>, match_number: u16, game_data: GameData, state: AppState) {
-    let (mut host_recv, user_sender, viewer_sender) = {
+/// Tells a host with an old cached page to reload.
+async fn outdated(mut ws: WebSocket) {
+    let _ = ws.send(Message::Binary(ClientboundHostPacket::Outdated(PROTOCOL_VERSION).into_bytes())).await;
+    let _ = ws.send(Message::Close(Some(CloseFrame { code: close_code::POLICY, reason: OUTDATED_REASON.into() }))).await;
+}
+
+async fn session_start(mut ws: WebSocket, scoreboard: ScoreboardId, blue_teams: Vec<String>, red_teams: Vec<String>, match_number: u16, game_data: GameData, state: AppState) {
+    let res = {
         let mut lock = state.lock().await;
-        let session = lock.new_session(session_id, blue_teams, red_teams, match_number, game_data.clone());
-        (session.host.sender.subscribe(), session.user.sender.clone(), session.viewer.sender.clone())
+        lock.new_session(scoreboard, blue_teams, red_teams, match_number, game_data.clone()).map(|(session_id, session)| {
+            (session_id, session.host.sender.subscribe(), session.user.sender.clone(), session.viewer.sender.clone(), session.join_code, session.ref_pin.clone())
+        })
+    };
+    let (session_id, mut host_recv, user_sender, viewer_sender, join_code, ref_pin) = match res {
+        Ok(res) => res,
+        Err(err) => {
+            error!("could not create session: {err}");
+            let _ = ws.send(Message::Close(Some(CloseFrame { code: close_code::POLICY, reason: err.to_string().into() }))).await;
+            return;
+        },
     };
 
-    if let Err(err) = ws.send(Message::Binary(ClientboundHostPacket::SessionInfo(session_id, game_data.clone()).into_bytes())).await {
+    if let Err(err) = ws.send(Message::Binary(ClientboundHostPacket::SessionInfo(join_code, ref_pin, game_data.clone()).into_bytes())).await {
         info!("[{session_id}] could not send info message! {err:?}");
         ws.close().await.expect("can close websocket");
         return;
     }
 
-    info!("[{session_id}] created");
+    info!("[{session_id}] created with join code {join_code}");
 
     let (mut sender, mut receiver) = ws.split();
     let (ws_send, mut ws_recv) = mpsc::unbounded_channel();
@@ -75,19 +108,16 @@ async fn session_start(mut ws: WebSocket, session_id: u32, blue_teams: Vec<Strin
                     break;
                 },
             };
-            let message = {
+            let sent = {
                 let mut manager = state.lock().await;
                 let Some(session) = manager.get_session_mut(session_id) else {
                     error!("[{session_id}] session already closed");
                     break;
                 };
                 let (viewer_message, message) = handle_host_message(message, session);
-                // an error means there are no viewers
-                let _ = viewer_sender.send(viewer_message);
-                message
+                send_update(&ws_send, &viewer_sender, viewer_message, message)
             };
-
-            if ws_send.send(Message::Binary(message.into_bytes())).is_err() { break; }
+            if sent.is_err() { break; }
         }
     };
 
@@ -95,76 +125,307 @@ async fn session_start(mut ws: WebSocket, session_id: u32, blue_teams: Vec<Strin
         while let Some(message) = receiver.next().await {
             match message {
                 Ok(Message::Binary(bytes)) => {
-                    if let Some(packet) = ServerboundHostPacket::from_bytes(bytes) {
-                        info!("[{session_id}] received message: {packet:?}");
-
-                        let message = match packet {
-                            ServerboundHostPacket::StartGame { time_started } => {
-                                {
-                                    let mut lock = state.lock().await;
-                                    let game_state = &mut lock.get_session_mut(session_id).expect("session exists").game_state;
-                                    if game_state.time_started.is_some() { continue; }
-                                    game_state.time_started = Some(time_started);
-                                };
-                                info!("[{session_id}] started game");
-                                viewer_sender.send(ViewerMessage::GameStart(time_started)).expect("receivers exist for viewer");
-                                Some(UserMessage::GameStart)
-                            },
-                            ServerboundHostPacket::EndGame => {
-                                {
-                                    let mut lock = state.lock().await;
-                                    let game_state = &mut lock.get_session_mut(session_id).expect("session exists").game_state;
-                                    if game_state.time_started.is_none() { continue; }
-                                    game_state.ended = true;
-                                };
-                                info!("[{session_id}] ended game");
-                                viewer_sender.send(ViewerMessage::GameEnd).expect("receivers exist for viewer");
-                                None
-                            },
-                            ServerboundHostPacket::RevealScore => {
-                                {
-                                    let mut lock = state.lock().await;
-                                    let game_state = &mut lock.get_session_mut(session_id).expect("session exists").game_state;
-                                    if !game_state.ended { continue; }
-                                    game_state.time_started = None;
-                                };
-                                info!("[{session_id}] revealed score");
-                                viewer_sender.send(ViewerMessage::RevealScore).expect("receivers exist for viewer");
-                                Some(UserMessage::GameEnd)
-                            },
-                            ServerboundHostPacket::PauseGame => {
-                                {
-                                    let mut lock = state.lock().await;
-                                    let game_state = &mut lock.get_session_mut(session_id).expect("session exists").game_state;
-                                    if game_state.paused { continue; }
-                                    game_state.paused = true;
-                                };
-                                info!("[{session_id}] paused game");
-                                viewer_sender.send(ViewerMessage::GamePause).expect("receivers exist for viewer");
-                                None
-                            },
-                            ServerboundHostPacket::UnpauseGame { time_paused } => {
-                                {
-                                    let mut lock = state.lock().await;
-                                    let game_state = &mut lock.get_session_mut(session_id).expect("session exists").game_state;
-                                    if !game_state.paused { continue; }
-                                    game_state.time_paused += time_paused;
-                                    game_state.paused = false;
-                                };
-                                info!("[{session_id}] unpaused game (paused for {}s)", time_paused / 1000);
-                                viewer_sender.send(ViewerMessage::GameUnpause(time_paused)).expect("receivers exist for viewer");
-                                None
-                            },
-                            ServerboundHostPacket::GameData { .. } => None,
-                        };
-
-                        if let Some(message) = message {
-                            if user_sender.send(message).is_err() { break; }
-                        };
-                    } else {
-                        error!("[{session_id}] malformed host packet");
-                        break;
-                    }
+                    let packet = match ServerboundHostPacket::from_bytes(bytes) {
+                        Ok(packet) => packet,
+                        Err(err) => {
+                            error!("[{session_id}] malformed host packet: {err}");
+                            if ws_send.send(Message::Binary(ClientboundHostPacket::Malformed(err.to_string()).into_bytes())).is_err() { break; }
+                            continue;
+                        },
+                    };
+                    info!("[{session_id}] received message: {packet:?}");
+
+                    let message = match packet {
+                        ServerboundHostPacket::StartGame { time_started } => {
+                            {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if session.game_state.time_started.is_some() { continue; }
+                                session.game_state.time_started = Some(time_started);
+                                session.started_at = Some(Instant::now());
+                            };
+                            info!("[{session_id}] started game");
+                            viewer_sender.send(ViewerMessage::GameStart(time_started)).expect("receivers exist for viewer");
+                            Some(UserMessage::GameStart)
+                        },
+                        ServerboundHostPacket::EndGame => {
+                            let grace_period = {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if session.game_state.time_started.is_none() || session.game_state.ended { continue; }
+                                session.game_state.ended = true;
+                                session.game_state.time_ended = session.match_time();
+                                session.ended_at = Some(Instant::now());
+                                session.grace_period
+                            };
+                            info!("[{session_id}] ended game");
+                            viewer_sender.send(ViewerMessage::GameEnd).expect("receivers exist for viewer");
+                            let user_sender = user_sender.clone();
+                            tokio::spawn(async move {
+                                tokio::time::sleep(grace_period).await;
+                                // an error means the session closed
+                                let _ = user_sender.send(UserMessage::ScoringClosed);
+                            });
+                            Some(UserMessage::GameEnd(grace_period.as_secs()))
+                        },
+                        ServerboundHostPacket::RevealScore => {
+                            let revealed = {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if !session.game_state.ended || session.game_state.revealed { continue; }
+                                if !session.discrepancies.is_empty() {
+                                    info!("[{session_id}] cannot reveal score with {} unresolved discrepancies", session.discrepancies.len());
+                                    Err(RejectReason::Disputed)
+                                } else if !session.pending_scores.is_empty() {
+                                    info!("[{session_id}] cannot reveal score with {} scores waiting for review", session.pending_scores.len());
+                                    Err(RejectReason::PendingReview)
+                                } else {
+                                    session.game_state.time_started = None;
+                                    session.game_state.revealed = true;
+                                    Ok(())
+                                }
+                            };
+                            let reply = match revealed {
+                                Ok(()) => ClientboundHostPacket::ScoreRevealed(),
+                                Err(reason) => ClientboundHostPacket::Rejected(reason),
+                            };
+                            if ws_send.send(Message::Binary(reply.into_bytes())).is_err() { break; }
+                            if revealed.is_err() { continue; }
+                            info!("[{session_id}] revealed score");
+                            viewer_sender.send(ViewerMessage::RevealScore).expect("receivers exist for viewer");
+                            Some(UserMessage::ScoringClosed)
+                        },
+                        ServerboundHostPacket::PauseGame => {
+                            let score_while_paused = {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if session.game_state.paused { continue; }
+                                session.game_state.paused = true;
+                                let paused_at = session.match_time().unwrap_or_default();
+                                session.game_state.pauses.push((paused_at, None));
+                                session.score_while_paused
+                            };
+                            info!("[{session_id}] paused game");
+                            viewer_sender.send(ViewerMessage::GamePause).expect("receivers exist for viewer");
+                            Some(UserMessage::Paused(true, score_while_paused))
+                        },
+                        ServerboundHostPacket::UnpauseGame { time_paused } => {
+                            let score_while_paused = {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if !session.game_state.paused { continue; }
+                                session.game_state.time_paused += time_paused;
+                                session.game_state.paused = false;
+                                let unpaused_at = session.match_time();
+                                if let Some((_, end)) = session.game_state.pauses.last_mut() {
+                                    *end = unpaused_at;
+                                }
+                                session.score_while_paused
+                            };
+                            info!("[{session_id}] unpaused game (paused for {}s)", time_paused / 1000);
+                            viewer_sender.send(ViewerMessage::GameUnpause(time_paused)).expect("receivers exist for viewer");
+                            Some(UserMessage::Paused(false, score_while_paused))
+                        },
+                        ServerboundHostPacket::SetDisplayMode { mode } => {
+                            {
+                                let mut lock = state.lock().await;
+                                lock.get_session_mut(session_id).expect("session exists").display_mode = mode;
+                            };
+                            info!("[{session_id}] set display mode to {mode:?}");
+                            viewer_sender.send(ViewerMessage::DisplayMode(mode)).expect("receivers exist for viewer");
+                            None
+                        },
+                        ServerboundHostPacket::KickRef { ref_id, ban } => {
+                            {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if ban {
+                                    let Some(ref_info) = session.ban_ref(ref_id) else { continue; };
+                                    info!("[{session_id}] banned ref {ref_id} ({})", ref_info.name);
+                                } else if !session.refs.contains_key(&ref_id) {
+                                    continue;
+                                }
+                            };
+                            info!("[{session_id}] kicked ref {ref_id}");
+                            Some(UserMessage::Kick(ref_id))
+                        },
+                        ServerboundHostPacket::SetScoringLocked { locked } => {
+                            {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if session.scoring_locked == locked { continue; }
+                                session.scoring_locked = locked;
+                            };
+                            info!("[{session_id}] {} scoring", if locked { "locked" } else { "unlocked" });
+                            Some(UserMessage::ScoringLocked(locked))
+                        },
+                        ServerboundHostPacket::SetScoreWhilePaused { allowed } => {
+                            let paused = {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if session.score_while_paused == allowed { continue; }
+                                session.score_while_paused = allowed;
+                                session.game_state.paused
+                            };
+                            info!("[{session_id}] {} scoring while paused", if allowed { "allowed" } else { "disallowed" });
+                            Some(UserMessage::Paused(paused, allowed))
+                        },
+                        ServerboundHostPacket::SetRefPin { pin } => {
+                            let pin = match pin.map(|pin| pin.trim().to_string()) {
+                                Some(pin) if !valid_pin(&pin) => continue,
+                                pin => pin,
+                            };
+                            let pin = {
+                                let mut lock = state.lock().await;
+                                lock.get_session_mut(session_id).expect("session exists").set_ref_pin(pin).to_string()
+                            };
+                            info!("[{session_id}] changed ref pin");
+                            if ws_send.send(Message::Binary(ClientboundHostPacket::RefPin(pin).into_bytes())).is_err() { break; }
+                            None
+                        },
+                        ServerboundHostPacket::CreateInvite { team, categories, expires_in } => {
+                            let scoreboard = {
+                                let lock = state.lock().await;
+                                let session = lock.get_session(session_id).expect("session exists");
+                                if !valid_categories(&session.game_data, &categories) { continue; }
+                                session.scoreboard
+                            };
+
+                            // random rather than counted, so invites from before a server restart keep their own ids
+                            let invite_id = rand::random();
+                            let expires_at = invite::now().saturating_add(expires_in);
+                            let token = Invite { scoreboard, invite_id, team, categories, expires_at }.sign();
+                            info!("[{session_id}] created invite {invite_id} for {team:?}");
+                            if ws_send.send(Message::Binary(ClientboundHostPacket::Invite(invite_id, team, expires_at, token).into_bytes())).is_err() { break; }
+                            None
+                        },
+                        ServerboundHostPacket::RevokeInvite { invite_id } => {
+                            let ref_ids = {
+                                let mut lock = state.lock().await;
+                                lock.revoke_invite(session_id, invite_id)
+                            };
+                            info!("[{session_id}] revoked invite {invite_id}");
+                            for ref_id in ref_ids {
+                                if user_sender.send(UserMessage::Kick(ref_id)).is_err() { break; }
+                            }
+                            None
+                        },
+                        ServerboundHostPacket::SetRefCategories { ref_id, categories } => {
+                            {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if !valid_categories(&session.game_data, &categories) { continue; }
+                                let Some(ref_info) = session.refs.get_mut(&ref_id) else { continue; };
+                                ref_info.categories = categories;
+                            };
+                            info!("[{session_id}] changed categories of ref {ref_id}");
+                            Some(UserMessage::RefUpdated(ref_id))
+                        },
+                        ServerboundHostPacket::SetReviewCategories { categories } => {
+                            {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if !valid_categories(&session.game_data, &categories) { continue; }
+                                session.review_categories = categories;
+                            };
+                            info!("[{session_id}] changed review categories");
+                            None
+                        },
+                        ServerboundHostPacket::ReviewScore { pending_id, approve } => {
+                            let (viewer_message, message, user_message) = {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                // the score is final once it's revealed
+                                if session.game_state.revealed { continue; }
+                                let Some(pending) = session.pending_scores.remove(&pending_id) else { continue; };
+                                if approve {
+                                    let (viewer_message, message) = score(session, pending);
+                                    (viewer_message, message, None)
+                                } else {
+                                    session.reject_score(&pending);
+                                    (None, None, Some(UserMessage::ScoreRejected(pending)))
+                                }
+                            };
+                            info!("[{session_id}] {} pending score {pending_id}", if approve { "approved" } else { "rejected" });
+                            if send_update(&ws_send, &viewer_sender, viewer_message, message).is_err() { break; }
+                            user_message
+                        },
+                        ServerboundHostPacket::SetConsensusMode { mode } => {
+                            {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if session.game_state.time_started.is_some() || session.game_state.ended { continue; }
+                                session.consensus_mode = mode;
+                            };
+                            info!("[{session_id}] set consensus mode to {mode:?}");
+                            None
+                        },
+                        ServerboundHostPacket::ResolveDiscrepancy { team, score_type, count } => {
+                            if count > MAX_SCORE_COUNT {
+                                if ws_send.send(Message::Binary(ClientboundHostPacket::Rejected(RejectReason::Malformed).into_bytes())).is_err() { break; }
+                                continue;
+                            }
+                            let counts = {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                let Some(counts) = session.resolve_discrepancy(team, score_type, count) else { continue; };
+                                counts
+                            };
+                            info!("[{session_id}] resolved {team:?} score {score_type} to {count}");
+                            let (viewer_message, message) = score_counts(team, score_type, counts, false);
+                            if send_update(&ws_send, &viewer_sender, viewer_message, Some(message)).is_err() { break; }
+                            None
+                        },
+                        ServerboundHostPacket::SetScoreCount { team, score_type, count, reason } => {
+                            let adjusted = {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                adjust_score(session, team, score_type, Some(count), reason)
+                            };
+                            if adjusted.is_ok() { info!("[{session_id}] set {team:?} score {score_type} to {count}"); }
+                            if send_adjustment(&ws_send, &viewer_sender, adjusted).is_err() { break; }
+                            None
+                        },
+                        ServerboundHostPacket::AdjustScore { team, score_type, undo, reason } => {
+                            let adjusted = {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                let count = session.game_state.count(team, score_type).max(0) as u32;
+                                let count = if undo { count.checked_sub(1) } else { count.checked_add(1) };
+                                adjust_score(session, team, score_type, count, reason)
+                            };
+                            if adjusted.is_ok() { info!("[{session_id}] {} one {team:?} score {score_type}", if undo { "removed" } else { "added" }); }
+                            if send_adjustment(&ws_send, &viewer_sender, adjusted).is_err() { break; }
+                            None
+                        },
+                        ServerboundHostPacket::ReopenScore => {
+                            {
+                                let mut lock = state.lock().await;
+                                let game_state = &mut lock.get_session_mut(session_id).expect("session exists").game_state;
+                                if !game_state.revealed { continue; }
+                                game_state.revealed = false;
+                            };
+                            info!("[{session_id}] reopened score");
+                            None
+                        },
+                        ServerboundHostPacket::SetGracePeriod { seconds } => {
+                            let grace_period = Duration::from_secs(seconds);
+                            if grace_period > MAX_GRACE_PERIOD { continue; }
+                            {
+                                let mut lock = state.lock().await;
+                                let session = lock.get_session_mut(session_id).expect("session exists");
+                                if session.game_state.ended { continue; }
+                                session.grace_period = grace_period;
+                            };
+                            info!("[{session_id}] set grace period to {seconds}s");
+                            None
+                        },
+                        ServerboundHostPacket::GameData { .. } => None,
+                    };
+
+                    if let Some(message) = message {
+                        if user_sender.send(message).is_err() { break; }
+                    };
                 },
                 Ok(_) => {},
                 Err(err) => {
@@ -189,22 +450,77 @@ async fn session_start(mut ws: WebSocket, session_id: u32, blue_teams: Vec<Strin
     info!("[{session_id}] disconnected");
 }
 
-fn handle_host_message(message: HostMessage, session: &mut Session) -> (ViewerMessage, ClientboundHostPacket) {
+fn valid_categories(game_data: &GameData, categories: &[String]) -> bool {
+    categories.iter().all(|category| game_data.score_points.iter().any(|score_point| &score_point.category == category))
+}
+
+/// Sets the official count of a score point by hand if the score is still open, returning what to send to viewers and the host.
+fn adjust_score(session: &mut Session, team: Team, score_id: u16, count: Option<u32>, reason: String) -> Result<(Option<ViewerMessage>, ClientboundHostPacket), RejectReason> {
+    let reason = reason.trim().to_string();
+    let count = count.ok_or(RejectReason::BelowZero)?;
+    if count > MAX_SCORE_COUNT || reason.is_empty() || reason.chars().count() > MAX_REASON_LEN { return Err(RejectReason::Malformed); }
+    if session.game_state.revealed { return Err(RejectReason::ScoringClosed); }
+    if session.game_data.score_points.get(score_id as usize).is_none() { return Err(RejectReason::InvalidScorePoint); }
+
+    let previous = session.adjust_score(team, score_id, count, reason.clone());
+    let time = session.adjustments.last().expect("adjustment was recorded").time;
+    let viewer_message = (previous != count as i32).then_some(ViewerMessage::ScoreSet(team, score_id, count));
+    Ok((viewer_message, ClientboundHostPacket::ScoreAdjusted(team, score_id, count, reason, time)))
+}
+
+/// Passes a change to the score on to viewers, if they need to know, and replies to the host.
+/// Fails once the host's socket is gone.
+fn send_update(ws_send: &UnboundedSender<Message>, viewer_sender: &broadcast::Sender<ViewerMessage>, viewer_message: Option<ViewerMessage>, message: Option<ClientboundHostPacket>) -> Result<(), SendError<Message>> {
+    if let Some(viewer_message) = viewer_message {
+        // an error means there are no viewers
+        let _ = viewer_sender.send(viewer_message);
+    }
+    if let Some(message) = message {
+        ws_send.send(Message::Binary(message.into_bytes()))?;
+    }
+    Ok(())
+}
+
+/// Replies to a hand adjustment of the score, telling the host why if it was rejected.
+fn send_adjustment(ws_send: &UnboundedSender<Message>, viewer_sender: &broadcast::Sender<ViewerMessage>, adjusted: Result<(Option<ViewerMessage>, ClientboundHostPacket), RejectReason>) -> Result<(), SendError<Message>> {
+    let (viewer_message, message) = adjusted.unwrap_or_else(|reason| (None, ClientboundHostPacket::Rejected(reason)));
+    send_update(ws_send, viewer_sender, viewer_message, Some(message))
+}
+
+fn valid_pin(pin: &str) -> bool {
+    (4..=8).contains(&pin.len()) && pin.chars().all(|char| char.is_ascii_digit())
+}
+
+fn handle_host_message(message: HostMessage, session: &mut Session) -> (Option<ViewerMessage>, Option<ClientboundHostPacket>) {
     match message {
-        HostMessage::Score(team, score_id, undo) => {
-            let scored = match team {
-                Team::Red => &mut session.game_state.red_scored,
-                Team::Blue => &mut session.game_state.blue_scored,
-            };
-            let scores = scored.entry(score_id).or_default();
-            if undo {
-                scores.undo += 1;
-            } else {
-                scores.scored += 1;
+        HostMessage::Score(ref_score) => {
+            if session.needs_review(ref_score.score_id) {
+                let RefScore { ref_id, team, score_id, undo, late, .. } = ref_score;
+                let pending_id = session.add_pending_score(ref_score);
+                return (None, Some(ClientboundHostPacket::PendingScore(pending_id, ref_id, team, score_id, undo, late)));
             }
 
-            (ViewerMessage::Score(team, score_id, undo), ClientboundHostPacket::Score(team, score_id, undo))
+            score(session, ref_score)
+        },
+        HostMessage::RefJoined(ref_id, RefInfo { name, role, team, categories, .. }) => (None, Some(ClientboundHostPacket::RefJoined(ref_id, name, role, team, categories))),
+        HostMessage::RefLeft(ref_id) => (None, Some(ClientboundHostPacket::RefLeft(ref_id))),
+    }
+}
+
+fn score(session: &mut Session, RefScore { ref_id, team, score_id, undo, late, .. }: RefScore) -> (Option<ViewerMessage>, Option<ClientboundHostPacket>) {
+    match session.score(ref_id, team, score_id, undo) {
+        Some(ScoreChange::Counted) => (Some(ViewerMessage::Score(team, score_id, undo)), Some(ClientboundHostPacket::Score(team, score_id, undo, late))),
+        Some(ScoreChange::Reconciled(counts)) => {
+            let (viewer_message, message) = score_counts(team, score_id, counts, late);
+            (viewer_message, Some(message))
         },
+        None => (None, None),
     }
 }
 
+fn score_counts(team: Team, score_id: u16, ScoreCounts { count, changed, disputed, ref_counts }: ScoreCounts, late: bool) -> (Option<ViewerMessage>, ClientboundHostPacket) {
+    let (ref_ids, counts) = ref_counts.into_iter().unzip();
+    let viewer_message = changed.then_some(ViewerMessage::ScoreSet(team, score_id, count));
+    (viewer_message, ClientboundHostPacket::ScoreCounts(team, score_id, count, disputed, ref_ids, counts, late))
+}
+
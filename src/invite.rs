@@ -0,0 +1,171 @@
+use std::{fmt::{Display, Formatter}, time::{SystemTime, UNIX_EPOCH}};
+
+use axum::{extract::{Path, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use rand::{thread_rng, RngCore};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{AppState, packet::{DecodeError, PacketReader, PacketWriter, Readable, Writable}, session_manager::Team};
+
+lazy_static! {
+    /// Key invites are signed with. Set `INVITE_SECRET` to keep invites valid across restarts.
+    /// Revoked invites are only remembered in memory, so after a restart a revoked invite works again until it
+    /// expires; change the secret to revoke every invite at once.
+    static ref SECRET: [u8; 32] = match std::env::var("INVITE_SECRET") {
+        Ok(secret) => Sha256::digest(secret.as_bytes()).into(),
+        Err(_) => {
+            let mut secret = [0; 32];
+            thread_rng().fill_bytes(&mut secret);
+            secret
+        },
+    };
+}
+
+/// Identifies a scoreboard (e.g. one field's host page) across its matches and reconnects, so invites can be handed
+/// out before the session they're used in exists.
+///
+/// It's a hash of a key only the host page knows, so refs reading it off an invite can't host as that scoreboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ScoreboardId(pub u64);
+
+impl ScoreboardId {
+    pub fn from_key(key: &str) -> Self {
+        let hash = Sha256::digest(key.as_bytes());
+        ScoreboardId(u64::from_le_bytes(hash[..8].try_into().expect("sha256 is longer than 8 bytes")))
+    }
+}
+
+impl Readable for ScoreboardId {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        reader.read().map(ScoreboardId)
+    }
+}
+
+impl Writable for ScoreboardId {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write(self.0);
+    }
+}
+
+/// A ref's invitation to join one alliance of whatever match a scoreboard is running, handed out as a signed token.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invite {
+    pub scoreboard: ScoreboardId,
+    pub invite_id: u32,
+    pub team: Team,
+    /// Score point categories the ref may score. Empty means every category.
+    pub categories: Vec<String>,
+    /// Unix timestamp (in seconds) after which the invite is no longer accepted.
+    pub expires_at: u64,
+}
+
+impl Invite {
+    pub fn sign(self) -> String {
+        let mut writer = PacketWriter::new();
+        writer.write(self);
+        let payload = writer.get();
+
+        let signature = mac().chain_update(&payload).finalize().into_bytes();
+        format!("{}.{}", URL_SAFE_NO_PAD.encode(payload), URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    /// Checks the signature and expiry of `token`. Whether the invite has been revoked is up to the session.
+    pub fn verify(token: &str) -> Result<Self, InviteError> {
+        let (payload, signature) = token.split_once('.').ok_or(InviteError::Malformed)?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| InviteError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| InviteError::Malformed)?;
+
+        mac().chain_update(&payload).verify_slice(&signature).map_err(|_| InviteError::BadSignature)?;
+
+        let mut reader = PacketReader::new(&payload).map_err(|_| InviteError::Malformed)?;
+        let invite: Invite = reader.read().map_err(|_| InviteError::Malformed)?;
+        if reader.has_next() { return Err(InviteError::Malformed); }
+
+        if invite.expires_at <= now() { return Err(InviteError::Expired); }
+        Ok(invite)
+    }
+}
+
+impl Readable for Invite {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let scoreboard = reader.read()?;
+        let invite_id = reader.read()?;
+        let team = reader.read()?;
+        let categories = reader.read()?;
+        let expires_at = reader.read()?;
+
+        Ok(Invite { scoreboard, invite_id, team, categories, expires_at })
+    }
+}
+
+impl Writable for Invite {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write(self.scoreboard);
+        writer.write(self.invite_id);
+        writer.write(self.team);
+        writer.write(self.categories);
+        writer.write(self.expires_at);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InviteError {
+    Malformed,
+    BadSignature,
+    Expired,
+    Revoked,
+    WrongScoreboard,
+    NoMatch,
+}
+
+impl Display for InviteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InviteError::Malformed => write!(f, "malformed invite"),
+            InviteError::BadSignature => write!(f, "invalid invite signature"),
+            InviteError::Expired => write!(f, "invite has expired"),
+            InviteError::Revoked => write!(f, "invite has been revoked"),
+            InviteError::WrongScoreboard => write!(f, "invite is for a different scoreboard or alliance"),
+            InviteError::NoMatch => write!(f, "no match is running for this invite yet"),
+        }
+    }
+}
+
+/// Where an invite's ref should join right now.
+#[derive(Serialize)]
+pub struct InviteTarget {
+    join_code: String,
+    team: Team,
+}
+
+/// Looks up the match an invite's scoreboard is running, since the invite was made without knowing its join code.
+pub async fn invite_handler(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    let invite = match Invite::verify(&token) {
+        Ok(invite) => invite,
+        Err(err) => return (StatusCode::FORBIDDEN, err.to_string()).into_response(),
+    };
+
+    let lock = state.lock().await;
+    if lock.is_invite_revoked(&invite) {
+        return (StatusCode::FORBIDDEN, InviteError::Revoked.to_string()).into_response();
+    }
+    match lock.get_session_by_scoreboard(invite.scoreboard) {
+        Some((_, session)) => Json(InviteTarget { join_code: session.join_code.to_string(), team: invite.team }).into_response(),
+        None => (StatusCode::NOT_FOUND, InviteError::NoMatch.to_string()).into_response(),
+    }
+}
+
+fn mac() -> Hmac<Sha256> {
+    Hmac::new_from_slice(SECRET.as_slice()).expect("hmac accepts any key length")
+}
+
+/// The current unix timestamp, in seconds.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("time is after the unix epoch").as_secs()
+}
@@ -0,0 +1,53 @@
+use std::fmt::{Display, Formatter};
+
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::packet::{PacketWriter, Writable};
+
+/// Characters a join code can be made of. Characters that are easily confused with each other (`0`/`O`, `1`/`I`/`L`) are left out.
+const ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+const LEN: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct JoinCode([u8; LEN]);
+
+impl JoinCode {
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let mut code = [0; LEN];
+        for char in code.iter_mut() {
+            *char = ALPHABET[rng.gen_range(0..ALPHABET.len())];
+        }
+        JoinCode(code)
+    }
+
+    pub fn parse(code: &str) -> Option<Self> {
+        let code: [u8; LEN] = code.trim().to_ascii_uppercase().into_bytes().try_into().ok()?;
+        code.iter().all(|char| ALPHABET.contains(char)).then_some(JoinCode(code))
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("join codes are ascii")
+    }
+}
+
+impl TryFrom<String> for JoinCode {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        JoinCode::parse(&value).ok_or("invalid join code")
+    }
+}
+
+impl Display for JoinCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Writable for JoinCode {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write(self.as_str().to_string());
+    }
+}
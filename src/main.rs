@@ -9,11 +9,15 @@ use tower_http::{services::ServeDir, trace::TraceLayer};
 use tracing::info;
 
 mod host;
+mod invite;
+mod join_code;
 mod session_manager;
 mod user;
 mod game;
 mod view;
 mod packet;
+mod qr;
+mod overlay;
 
 pub type AppState = Arc<Mutex<SessionManager>>;
 
@@ -24,19 +28,23 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let port = std::env::var("PORT")
         .map(|port| port.parse())
         .unwrap_or(Ok(3000u16))?;
+    packet::DecodeLimits::from_env()?.install();
 
     let router = Router::new()
         .fallback_service(ServeDir::new("public"))
         .route("/api/builtin-games", get(game::get_all_builtin))
+        .route("/api/qr/{join_code}/{target}", get(qr::qr_handler))
+        .route("/api/invite/{token}", get(invite::invite_handler))
         .route("/ws/host", get(host::ws_handler))
-        .route("/ws/join/{id}/{team_id}", get(user::ws_handler))
-        .route("/sse/view/{id}", get(view::sse_handler))
+        .route("/ws/join/{join_code}/{team_id}", get(user::ws_handler))
+        .route("/sse/view/{join_code}", get(view::sse_handler))
+        .route("/overlay/{join_code}/{layout}", get(overlay::overlay_handler))
         .with_state(Arc::new(Mutex::new(SessionManager::new())))
         .layer(TraceLayer::new_for_http());
 
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     info!("listening on {addr}...");
-    Ok(axum::serve(listener, router).await?)
+    Ok(axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>()).await?)
 }
 
@@ -0,0 +1,38 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::{Html, IntoResponse, Response}};
+use serde::Deserialize;
+use tracing::error;
+
+use crate::{AppState, join_code::JoinCode};
+
+const PAGE: &str = "public/overlay/index.html";
+
+/// What an overlay shows, so each one can be its own browser source.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayLayout {
+    /// A lower-third bar with each alliance's teams and score, the match number and the time left.
+    ScoreBar,
+    /// Full-screen results, shown once the host reveals the score.
+    Results,
+    /// Only the time left in the match.
+    Timer,
+}
+
+/// Serves the overlay page for a session's layout. The page itself picks the layout from its path and
+/// follows the session through the same feed as the audience display.
+pub async fn overlay_handler(
+    Path((join_code, _layout)): Path<(JoinCode, OverlayLayout)>,
+    State(state): State<AppState>,
+) -> Response {
+    if state.lock().await.get_session_by_join_code(join_code).is_none() {
+        return (StatusCode::BAD_REQUEST, "invalid join code").into_response();
+    }
+
+    match tokio::fs::read_to_string(PAGE).await {
+        Ok(page) => Html(page).into_response(),
+        Err(err) => {
+            error!("couldn't read {PAGE}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        },
+    }
+}
@@ -1,6 +1,89 @@
+use std::{env, error::Error, fmt::{self, Display, Formatter}, sync::OnceLock};
+
 use axum::body::Bytes;
 
-use crate::{game::{GameData, BuiltinGame}, session_manager::Team};
+use crate::{game::{GameData, BuiltinGame}, join_code::JoinCode, session_manager::{ConsensusMode, DisplayMode, OfflineScore, RefRole, RejectReason, Team}};
+
+/// The version of the packet format, sent by clients when they connect so outdated ones can be told to reload.
+/// Bump this whenever a packet changes.
+pub const PROTOCOL_VERSION: u16 = 10;
+pub const OUTDATED_REASON: &str = "this page is out of date, please reload it";
+
+static LIMITS: OnceLock<DecodeLimits> = OnceLock::new();
+
+/// How much a [`PacketReader`] will decode, so a hostile packet can't make the server allocate more than it should.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// The most bytes a packet can have. Larger packets don't decode at all.
+    pub max_packet_len: usize,
+    /// The most bytes a string can have.
+    pub max_string_len: usize,
+    /// The most elements a list can have.
+    pub max_list_len: usize,
+}
+
+impl DecodeLimits {
+    /// Reads the limits from the `MAX_PACKET_LEN`, `MAX_STRING_LEN` and `MAX_LIST_LEN` environment variables,
+    /// falling back to the defaults for any that aren't set.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let default = Self::default();
+        let var = |name, default| env::var(name).map(|value| value.parse()).unwrap_or(Ok(default));
+        Ok(Self {
+            max_packet_len: var("MAX_PACKET_LEN", default.max_packet_len)?,
+            max_string_len: var("MAX_STRING_LEN", default.max_string_len)?,
+            max_list_len: var("MAX_LIST_LEN", default.max_list_len)?,
+        })
+    }
+
+    /// Sets the limits every packet is decoded with. Can only be done once, before any packets are read.
+    pub fn install(self) {
+        LIMITS.set(self).expect("limits are only installed once");
+    }
+
+    /// The limits packets are decoded with.
+    pub fn current() -> Self {
+        *LIMITS.get_or_init(Self::default)
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self { max_packet_len: 64 * 1024, max_string_len: 1024, max_list_len: 1024 }
+    }
+}
+
+/// Why a packet couldn't be decoded. Offsets are in bytes from the start of the packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEof { offset: usize },
+    UnknownPacket { id: u8 },
+    InvalidUtf8 { offset: usize },
+    InvalidVariant { name: &'static str, variant: u32, offset: usize },
+    LimitExceeded { what: &'static str, len: usize, max: usize, offset: usize },
+    /// Another error, in a field of a packet.
+    InField { field: &'static str, error: Box<DecodeError> },
+}
+
+impl DecodeError {
+    pub fn in_field(self, field: &'static str) -> Self {
+        DecodeError::InField { field, error: Box::new(self) }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof { offset } => write!(f, "packet ended early at byte {offset}"),
+            DecodeError::UnknownPacket { id } => write!(f, "unknown packet id {id}"),
+            DecodeError::InvalidUtf8 { offset } => write!(f, "invalid utf-8 in string at byte {offset}"),
+            DecodeError::InvalidVariant { name, variant, offset } => write!(f, "invalid {name} {variant} at byte {offset}"),
+            DecodeError::LimitExceeded { what, len, max, offset } => write!(f, "{what} of length {len} at byte {offset} is longer than {max}"),
+            DecodeError::InField { field, error } => write!(f, "{field}: {error}"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
 
 macro_rules! clientbound_packet {
     ($n: ident { $($i: literal : $v: ident $(($($m: ident: $t: ty),+))? ),* $(,)?}) => {
@@ -43,19 +126,19 @@ macro_rules! serverbound_packet {
         }
 
         impl ServerboundPacket for $n {
-            fn read(reader: &mut PacketReader) -> Option<Self> {
+            fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> {
                 let id: u8 = reader.read()?;
 
                 match id {
                     $(
                         $i => {
                             $($(
-                                let $f = reader.read()?;
+                                let $f = reader.read().map_err(|err| err.in_field(concat!(stringify!($v), ".", stringify!($f))))?;
                             )*)?
-                            Some(Self::$v $({ $($f),* })?)
+                            Ok(Self::$v $({ $($f),* })?)
                         }
                     )*
-                    _ => None,
+                    id => Err(DecodeError::UnknownPacket { id }),
                 }
             }
         }
@@ -64,16 +147,37 @@ macro_rules! serverbound_packet {
 
 clientbound_packet! {
     ClientboundHostPacket {
-        0: SessionInfo(id: u32, game_data: GameData),
-        1: Score(team: Team, score_type: u8, undo: bool),
+        0: SessionInfo(join_code: JoinCode, ref_pin: String, game_data: GameData),
+        1: Score(team: Team, score_type: u16, undo: bool, late: bool),
+        2: RefJoined(ref_id: u32, name: String, role: RefRole, team: Team, categories: Vec<String>),
+        3: RefLeft(ref_id: u32),
+        4: RefPin(pin: String),
+        5: Invite(invite_id: u32, team: Team, expires_at: u64, token: String),
+        6: PendingScore(pending_id: u32, ref_id: u32, team: Team, score_type: u16, undo: bool, late: bool),
+        7: ScoreCounts(team: Team, score_type: u16, count: u32, disputed: bool, ref_ids: Vec<u32>, counts: Vec<u32>, late: bool),
+        8: ScoreAdjusted(team: Team, score_type: u16, count: u32, reason: String, time: u64),
+        9: Rejected(reason: RejectReason),
+        10: Malformed(error: String),
+        11: ScoreRevealed,
+        // stays the same across versions so any client can tell it needs to reload
+        255: Outdated(version: u16),
     }
 }
 
 clientbound_packet! {
     ClientboundUserPacket {
-        0: SessionInfo(started: bool, game_data: GameData),
+        0: SessionInfo(started: bool, ended: bool, paused: bool, score_while_paused: bool, elapsed: u64, locked: bool, score_ids: Vec<u16>, counts: Vec<u32>, game_data: GameData),
         1: StartGame,
-        2: EndGame,
+        2: EndGame(grace_period: u64),
+        3: ScoringLocked(locked: bool),
+        4: ScoringClosed,
+        5: ScoreAck(seq: u32, event_id: u32, score_type: u16),
+        6: UndoAck(seq: u32, event_id: u32, score_type: u16),
+        7: Rejected(seq: Option<u32>, reason: RejectReason),
+        8: Paused(paused: bool, score_while_paused: bool),
+        9: Malformed(error: String),
+        10: ScoreRejected(event_id: u32, score_type: u16, undo: bool),
+        255: Outdated(version: u16),
     }
 }
 
@@ -83,14 +187,33 @@ serverbound_packet! {
         1: EndGame,
         2: PauseGame,
         3: UnpauseGame { time_paused: u64 },
-        4: GameData { match_number: u16, blue_teams: Vec<String>, red_teams: Vec<String>, game_type: Either<&'static BuiltinGame, GameData> },
+        4: GameData { version: u16, scoreboard_key: String, match_number: u16, blue_teams: Vec<String>, red_teams: Vec<String>, game_type: Either<&'static BuiltinGame, GameData> },
         5: RevealScore,
+        6: SetDisplayMode { mode: DisplayMode },
+        7: KickRef { ref_id: u32, ban: bool },
+        8: SetScoringLocked { locked: bool },
+        9: SetRefPin { pin: Option<String> },
+        10: CreateInvite { team: Team, categories: Vec<String>, expires_in: u64 },
+        11: RevokeInvite { invite_id: u32 },
+        12: SetRefCategories { ref_id: u32, categories: Vec<String> },
+        13: SetReviewCategories { categories: Vec<String> },
+        14: ReviewScore { pending_id: u32, approve: bool },
+        15: SetConsensusMode { mode: ConsensusMode },
+        16: ResolveDiscrepancy { team: Team, score_type: u16, count: u32 },
+        17: SetScoreCount { team: Team, score_type: u16, count: u32, reason: String },
+        18: AdjustScore { team: Team, score_type: u16, undo: bool, reason: String },
+        19: ReopenScore,
+        20: SetGracePeriod { seconds: u64 },
+        21: SetScoreWhilePaused { allowed: bool },
     }
 }
 
 serverbound_packet! {
     ServerboundUserPacket {
-        0: Score { score_type: u8, undo: bool },
+        0: Score { seq: u32, score_type: u16 },
+        1: Join { version: u16, name: String, role: RefRole, pin: String, client_id: String },
+        2: Undo { seq: u32, event_id: Option<u32> },
+        3: ScoreBatch { entries: Vec<OfflineScore> },
     }
 }
 
@@ -101,16 +224,23 @@ pub enum Either<L, R> {
 }
 
 impl<L: Readable, R: Readable> Readable for Either<L, R> {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
-        let variant: u8 = reader.read()?;
-        match variant {
-            0 => Some(Either::Left(L::read(reader)?)),
-            1 => Some(Either::Right(R::read(reader)?)),
-            _ => None,
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let offset = reader.offset();
+        match reader.read::<u8>()? {
+            0 => Ok(Either::Left(L::read(reader)?)),
+            1 => Ok(Either::Right(R::read(reader)?)),
+            variant => Err(DecodeError::InvalidVariant { name: "either", variant: variant.into(), offset }),
         }
     }
 }
 
+impl<T: Readable> Readable for Option<T> {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let present: bool = reader.read()?;
+        if present { Ok(Some(T::read(reader)?)) } else { Ok(None) }
+    }
+}
+
 pub trait IntoBytes {
     fn into_bytes(self) -> Bytes;
 }
@@ -127,14 +257,14 @@ where T: ClientboundPacket
 }
 
 pub trait FromBytes {
-    fn from_bytes(bytes: Bytes) -> Option<Self> where Self: Sized;
+    fn from_bytes(bytes: Bytes) -> Result<Self, DecodeError> where Self: Sized;
 }
 
 impl<T> FromBytes for T
 where T: ServerboundPacket
 {
-    fn from_bytes(bytes: Bytes) -> Option<Self> {
-        T::read(&mut PacketReader::new(bytes.as_ref()))
+    fn from_bytes(bytes: Bytes) -> Result<Self, DecodeError> {
+        T::read(&mut PacketReader::new(bytes.as_ref())?)
     }
 }
 
@@ -145,66 +275,86 @@ pub trait ClientboundPacket {
 }
 
 pub trait ServerboundPacket {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized;
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized;
 }
 
 pub trait Readable {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized;
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized;
 }
 
 impl Readable for bool {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
         reader.read_u8().map(|num| num != 0)
     }
 }
 
 impl Readable for u8 {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
         reader.read_u8()
     }
 }
 
 impl Readable for i8 {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
-        Some(i8::from_le_bytes(reader.read_n()?))
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        Ok(i8::from_le_bytes(reader.read_n()?))
     }
 }
 
 impl Readable for u16 {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
-        Some(u16::from_le_bytes(reader.read_n()?))
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        Ok(u16::from_le_bytes(reader.read_n()?))
+    }
+}
+
+impl Readable for i16 {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        Ok(i16::from_le_bytes(reader.read_n()?))
+    }
+}
+
+impl Readable for u32 {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        Ok(u32::from_le_bytes(reader.read_n()?))
+    }
+}
+
+impl Readable for i32 {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        Ok(i32::from_le_bytes(reader.read_n()?))
     }
 }
 
 impl Readable for u64 {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
-        Some(u64::from_le_bytes(reader.read_n()?))
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        Ok(u64::from_le_bytes(reader.read_n()?))
     }
 }
 
-impl Readable for usize {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
-        Some(usize::from_le_bytes(reader.read_n()?))
+impl Readable for i64 {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        Ok(i64::from_le_bytes(reader.read_n()?))
     }
 }
 
 impl Readable for String {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
-        let len = reader.read()?;
-        String::from_utf8(reader.read_n_slice(len)?.to_vec()).ok()
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let len = reader.read_len(reader.limits.max_string_len, "string")?;
+        let offset = reader.offset();
+        String::from_utf8(reader.read_n_slice(len)?.to_vec()).map_err(|_| DecodeError::InvalidUtf8 { offset })
     }
 }
 
 impl<T: Readable> Readable for Vec<T> {
-    fn read(reader: &mut PacketReader) -> Option<Self> where Self: Sized {
-        let len: usize = reader.read()?;
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let len = reader.read_len(reader.limits.max_list_len, "list")?;
 
-        let mut vec = Vec::with_capacity(len);
+        // every element takes at least a byte, so don't trust the length beyond what's left
+        let mut vec = Vec::with_capacity(len.min(reader.remaining()));
         for _ in 0..len {
             vec.push(reader.read()?);
         }
 
-        Some(vec)
+        Ok(vec)
     }
 }
 
@@ -236,13 +386,31 @@ impl Writable for u16 {
     }
 }
 
+impl Writable for i16 {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write_all(self.to_le_bytes());
+    }
+}
+
 impl Writable for u32 {
     fn write(self, writer: &mut PacketWriter) {
         writer.write_all(self.to_le_bytes());
     }
 }
 
-impl Writable for usize {
+impl Writable for i32 {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write_all(self.to_le_bytes());
+    }
+}
+
+impl Writable for u64 {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write_all(self.to_le_bytes());
+    }
+}
+
+impl Writable for i64 {
     fn write(self, writer: &mut PacketWriter) {
         writer.write_all(self.to_le_bytes());
     }
@@ -250,14 +418,23 @@ impl Writable for usize {
 
 impl Writable for String {
     fn write(self, writer: &mut PacketWriter) {
-        writer.write(self.len());
+        writer.write_len(self.len());
         writer.write_all(self.into_bytes());
     }
 }
 
+impl<T: Writable> Writable for Option<T> {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write(self.is_some());
+        if let Some(value) = self {
+            writer.write(value);
+        }
+    }
+}
+
 impl<T: Writable> Writable for Vec<T> {
     fn write(self, writer: &mut PacketWriter) {
-        writer.write(self.len());
+        writer.write_len(self.len());
         writer.write_all(self);
     }
 }
@@ -265,36 +442,59 @@ impl<T: Writable> Writable for Vec<T> {
 pub struct PacketReader<'a> {
     index: usize,
     bytes: &'a [u8],
+    limits: DecodeLimits,
 }
 
 impl<'a> PacketReader<'a> {
-    pub fn new(bytes: &'a [u8]) -> Self {
-        Self { index: 0, bytes }
+    pub fn new(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        Self::with_limits(bytes, DecodeLimits::current())
+    }
+
+    /// Fails if the whole packet is already larger than `limits` allow.
+    pub fn with_limits(bytes: &'a [u8], limits: DecodeLimits) -> Result<Self, DecodeError> {
+        if bytes.len() > limits.max_packet_len {
+            return Err(DecodeError::LimitExceeded { what: "packet", len: bytes.len(), max: limits.max_packet_len, offset: 0 });
+        }
+        Ok(Self { index: 0, bytes, limits })
     }
 
-    pub fn read<T: Readable>(&mut self) -> Option<T> {
+    pub fn read<T: Readable>(&mut self) -> Result<T, DecodeError> {
         T::read(self)
     }
 
-    pub fn read_u8(&mut self) -> Option<u8> {
-        let u8 = self.bytes.get(self.index).copied();
-        self.index += 1;
-        u8
+    /// Reads the length of a string or list, which is always a `u32` so it doesn't depend on the platform's `usize`.
+    pub fn read_len(&mut self, max: usize, what: &'static str) -> Result<usize, DecodeError> {
+        let offset = self.index;
+        let len = self.read::<u32>()? as usize;
+        if len > max { return Err(DecodeError::LimitExceeded { what, len, max, offset }); }
+        Ok(len)
     }
 
-    pub fn read_n<const S: usize>(&mut self) -> Option<[u8; S]> {
-        let mut bytes = [0; S];
-        for byte in bytes.iter_mut() {
-            *byte = self.read()?;
-        }
-        Some(bytes)
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.read_n_slice(1)?[0])
+    }
+
+    pub fn read_n<const S: usize>(&mut self) -> Result<[u8; S], DecodeError> {
+        Ok(self.read_n_slice(S)?.try_into().expect("slice has S bytes"))
+    }
+
+    /// Reads the next `len` bytes, which may run right up to the end of the packet but not past it.
+    pub fn read_n_slice(&mut self, len: usize) -> Result<&[u8], DecodeError> {
+        let end = self.index.checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DecodeError::UnexpectedEof { offset: self.index })?;
+        let slice = &self.bytes[self.index..end];
+        self.index = end;
+        Ok(slice)
+    }
+
+    /// How many bytes have been read so far.
+    pub fn offset(&self) -> usize {
+        self.index
     }
 
-    pub fn read_n_slice(&mut self, len: usize) -> Option<&[u8]> {
-        if self.index + len >= self.bytes.len() { return None };
-        let slice = &self.bytes[self.index..self.index + len];
-        self.index += len;
-        Some(slice)
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.index
     }
 
     pub fn has_next(&self) -> bool {
@@ -319,6 +519,11 @@ impl PacketWriter {
         data.into_iter().for_each(|writable| self.write(writable));
     }
 
+    /// Writes the length of a string or list as a `u32`, the counterpart to [`PacketReader::read_len`].
+    pub fn write_len(&mut self, len: usize) {
+        self.write(u32::try_from(len).expect("length fits in a u32"));
+    }
+
     pub fn write_u8(&mut self, data: u8) {
         self.bytes.push(data);
     }
@@ -328,3 +533,262 @@ impl PacketWriter {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use proptest::{collection::vec, prelude::*};
+
+    use crate::{game::{GameData, GameDuration, ScorePoint, MAX_SCORE_POINTS}, invite::{Invite, ScoreboardId}, session_manager::{RefRole, Team}};
+
+    use super::{DecodeError, DecodeLimits, FromBytes, PacketReader, PacketWriter, Readable, ServerboundHostPacket, ServerboundPacket, ServerboundUserPacket, Writable, PROTOCOL_VERSION};
+
+    const NO_LIMITS: DecodeLimits = DecodeLimits { max_packet_len: usize::MAX, max_string_len: usize::MAX, max_list_len: usize::MAX };
+
+    fn join(name: &str) -> Vec<u8> {
+        let mut writer = PacketWriter::new();
+        writer.write(1u8);
+        writer.write(PROTOCOL_VERSION);
+        writer.write(name.to_string());
+        writer.write(0u8);
+        writer.write("1234".to_string());
+        writer.write("client".to_string());
+        writer.get()
+    }
+
+    fn read_with_limits(bytes: &[u8], limits: DecodeLimits) -> Result<ServerboundUserPacket, DecodeError> {
+        ServerboundUserPacket::read(&mut PacketReader::with_limits(bytes, limits)?)
+    }
+
+    fn in_field(field: &'static str, error: DecodeError) -> DecodeError {
+        error.in_field(field)
+    }
+
+    /// Writes a value and reads it back, checking that it comes back the same and uses up every byte.
+    fn round_trip<T: Readable + Writable + Clone + PartialEq + Debug>(value: T) -> Result<(), TestCaseError> {
+        let mut writer = PacketWriter::new();
+        writer.write(value.clone());
+        let bytes = writer.get();
+
+        let mut reader = PacketReader::with_limits(&bytes, NO_LIMITS).unwrap();
+        prop_assert_eq!(reader.read::<T>(), Ok(value));
+        prop_assert!(!reader.has_next());
+        Ok(())
+    }
+
+    fn score_point() -> impl Strategy<Value = ScorePoint> {
+        (any::<String>(), any::<String>(), any::<i8>()).prop_map(|(name, category, points)| ScorePoint { name, category, points })
+    }
+
+    fn game_data(max_score_points: usize) -> impl Strategy<Value = GameData> {
+        (any::<u16>(), vec(score_point(), 0..=max_score_points))
+            .prop_map(|(secs, score_points)| GameData { duration: GameDuration { secs }, score_points: score_points.into() })
+    }
+
+    fn team() -> impl Strategy<Value = Team> {
+        prop_oneof![Just(Team::Blue), Just(Team::Red)]
+    }
+
+    fn ref_role() -> impl Strategy<Value = RefRole> {
+        prop_oneof![Just(RefRole::Scorer), Just(RefRole::Penalty), Just(RefRole::Head)]
+    }
+
+    fn invite() -> impl Strategy<Value = Invite> {
+        (any::<u64>(), any::<u32>(), team(), vec(any::<String>(), 0..4), any::<u64>())
+            .prop_map(|(scoreboard, invite_id, team, categories, expires_at)| Invite { scoreboard: ScoreboardId(scoreboard), invite_id, team, categories, expires_at })
+    }
+
+    #[test]
+    fn strings_can_end_the_packet() {
+        let mut writer = PacketWriter::new();
+        writer.write("end".to_string());
+        let bytes = writer.get();
+        let mut reader = PacketReader::new(&bytes).unwrap();
+        assert_eq!(reader.read::<String>(), Ok("end".to_string()));
+        assert_eq!(reader.read::<u8>(), Err(DecodeError::UnexpectedEof { offset: bytes.len() }));
+    }
+
+    #[test]
+    fn eof_is_reported_where_the_read_started() {
+        let mut reader = PacketReader::new(&[1, 2, 3]).unwrap();
+        assert_eq!(reader.read::<u8>(), Ok(1));
+        assert_eq!(reader.read::<u32>(), Err(DecodeError::UnexpectedEof { offset: 1 }));
+
+        let mut bytes = join("name");
+        bytes.truncate(bytes.len() - 2);
+        assert_eq!(ServerboundUserPacket::from_bytes(bytes.into()).err(), Some(in_field("Join.client_id", DecodeError::UnexpectedEof { offset: 24 })));
+    }
+
+    #[test]
+    fn game_data_can_be_followed_by_other_fields() {
+        let game_data = GameData { duration: GameDuration { secs: 150 }, score_points: Box::new([ScorePoint { name: "amp".to_string(), category: "amp".to_string(), points: 1 }]) };
+        let mut writer = PacketWriter::new();
+        writer.write(game_data.clone());
+        writer.write(7u32);
+        let bytes = writer.get();
+
+        let mut reader = PacketReader::new(&bytes).unwrap();
+        assert_eq!(reader.read::<GameData>(), Ok(game_data));
+        assert_eq!(reader.read::<u32>(), Ok(7));
+    }
+
+    #[test]
+    fn game_data_has_at_most_one_score_point_per_id() {
+        let score_point = ScorePoint { name: String::new(), category: String::new(), points: 1 };
+        let game_data = |len| GameData { duration: GameDuration { secs: 150 }, score_points: vec![score_point.clone(); len].into() };
+
+        // only the protocol's own limit applies here, not the decode limits
+        let limits = DecodeLimits { max_packet_len: usize::MAX, max_list_len: usize::MAX, ..Default::default() };
+
+        let mut writer = PacketWriter::new();
+        writer.write(game_data(MAX_SCORE_POINTS));
+        assert!(PacketReader::with_limits(&writer.get(), limits).unwrap().read::<GameData>().is_ok());
+
+        let mut writer = PacketWriter::new();
+        writer.write(game_data(MAX_SCORE_POINTS + 1));
+        assert_eq!(
+            PacketReader::with_limits(&writer.get(), limits).unwrap().read::<GameData>(),
+            Err(DecodeError::LimitExceeded { what: "score point list", len: MAX_SCORE_POINTS + 1, max: MAX_SCORE_POINTS, offset: 2 }),
+        );
+    }
+
+    #[test]
+    fn limits_reject_long_strings() {
+        let limits = DecodeLimits { max_string_len: 6, ..Default::default() };
+        let bytes = join("six ch");
+        assert!(read_with_limits(&bytes, limits).is_ok());
+
+        let bytes = join("seven c");
+        assert_eq!(
+            read_with_limits(&bytes, limits).err(),
+            Some(in_field("Join.name", DecodeError::LimitExceeded { what: "string", len: 7, max: 6, offset: 3 })),
+        );
+    }
+
+    #[test]
+    fn limits_reject_large_packets() {
+        let bytes = join("name");
+        let limits = DecodeLimits { max_packet_len: bytes.len() - 1, ..Default::default() };
+        assert_eq!(
+            read_with_limits(&bytes, limits).err(),
+            Some(DecodeError::LimitExceeded { what: "packet", len: bytes.len(), max: bytes.len() - 1, offset: 0 }),
+        );
+    }
+
+    #[test]
+    fn huge_lengths_do_not_allocate() {
+        // a batch of u32::MAX offline scores with nothing after it
+        let mut writer = PacketWriter::new();
+        writer.write(3u8);
+        writer.write(u32::MAX);
+        let bytes = writer.get();
+        assert_eq!(
+            ServerboundUserPacket::from_bytes(bytes.clone().into()).err(),
+            Some(in_field("ScoreBatch.entries", DecodeError::LimitExceeded { what: "list", len: u32::MAX as usize, max: 1024, offset: 1 })),
+        );
+
+        // even without a list limit, the length can't reserve more than the packet could hold
+        let limits = DecodeLimits { max_list_len: usize::MAX, ..Default::default() };
+        assert_eq!(
+            read_with_limits(&bytes, limits).err(),
+            Some(in_field("ScoreBatch.entries", DecodeError::UnexpectedEof { offset: 5 })),
+        );
+
+        let limits = DecodeLimits { max_string_len: usize::MAX, ..Default::default() };
+        let mut writer = PacketWriter::new();
+        writer.write(1u8);
+        writer.write(PROTOCOL_VERSION);
+        writer.write(u32::MAX);
+        assert_eq!(
+            read_with_limits(&writer.get(), limits).err(),
+            Some(in_field("Join.name", DecodeError::UnexpectedEof { offset: 7 })),
+        );
+    }
+
+    #[test]
+    fn errors_say_what_went_wrong() {
+        assert_eq!(ServerboundUserPacket::from_bytes(vec![200].into()).err(), Some(DecodeError::UnknownPacket { id: 200 }));
+
+        let mut bytes = join("name");
+        bytes[7] = 0xff;
+        assert_eq!(ServerboundUserPacket::from_bytes(bytes.into()).err(), Some(in_field("Join.name", DecodeError::InvalidUtf8 { offset: 7 })));
+
+        let mut bytes = join("name");
+        bytes[11] = 9;
+        assert_eq!(
+            ServerboundUserPacket::from_bytes(bytes.into()).err(),
+            Some(in_field("Join.role", DecodeError::InvalidVariant { name: "ref role", variant: 9, offset: 11 })),
+        );
+        assert_eq!(
+            in_field("Join.role", DecodeError::InvalidVariant { name: "ref role", variant: 9, offset: 11 }).to_string(),
+            "Join.role: invalid ref role 9 at byte 11",
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn integers_round_trip(a in any::<u8>(), b in any::<i8>(), c in any::<u16>(), d in any::<i16>(), e in any::<u32>(), f in any::<i32>(), g in any::<u64>(), h in any::<i64>(), i in any::<bool>()) {
+            round_trip(a)?;
+            round_trip(b)?;
+            round_trip(c)?;
+            round_trip(d)?;
+            round_trip(e)?;
+            round_trip(f)?;
+            round_trip(g)?;
+            round_trip(h)?;
+            round_trip(i)?;
+        }
+
+        #[test]
+        fn strings_round_trip(string in any::<String>()) {
+            round_trip(string)?;
+        }
+
+        #[test]
+        fn options_and_lists_round_trip(option in any::<Option<u32>>(), list in vec(any::<Option<String>>(), 0..16), nested in vec(vec(any::<i64>(), 0..4), 0..4)) {
+            round_trip(option)?;
+            round_trip(list)?;
+            round_trip(nested)?;
+        }
+
+        #[test]
+        fn enums_round_trip(team in team(), ref_role in ref_role()) {
+            round_trip(team)?;
+            round_trip(ref_role)?;
+        }
+
+        #[test]
+        fn game_data_round_trips(game_data in game_data(16), score_point in score_point(), secs in any::<u16>()) {
+            round_trip(game_data)?;
+            round_trip(score_point)?;
+            round_trip(GameDuration { secs })?;
+        }
+
+        #[test]
+        fn invites_round_trip(invite in invite()) {
+            round_trip(invite)?;
+        }
+
+        #[test]
+        fn host_packets_decode_any_bytes(bytes in vec(any::<u8>(), 0..512)) {
+            let _ = ServerboundHostPacket::from_bytes(bytes.into());
+        }
+
+        #[test]
+        fn user_packets_decode_any_bytes(bytes in vec(any::<u8>(), 0..512)) {
+            let _ = ServerboundUserPacket::from_bytes(bytes.into());
+        }
+
+        // starting with a real packet id gets past the first byte far more often than random bytes do
+        #[test]
+        fn host_packets_decode_any_body(id in 0u8..22, body in vec(any::<u8>(), 0..512)) {
+            let _ = ServerboundHostPacket::from_bytes([&[id], body.as_slice()].concat().into());
+        }
+
+        #[test]
+        fn user_packets_decode_any_body(id in 0u8..4, body in vec(any::<u8>(), 0..512)) {
+            let _ = ServerboundUserPacket::from_bytes([&[id], body.as_slice()].concat().into());
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::io::Cursor;
+
+use axum::{extract::{Path, Query, State}, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Response}};
+use image::{ImageFormat, Luma};
+use lazy_static::lazy_static;
+use qrcode::{render::svg, QrCode};
+use serde::Deserialize;
+
+use crate::{AppState, join_code::JoinCode, user::TRUST_PROXY};
+
+lazy_static! {
+    /// Where refs and viewers reach the server, e.g. `https://scores.example.org`. Set `PUBLIC_URL` so join links
+    /// don't depend on the request's `Host` header, which the client controls.
+    static ref PUBLIC_URL: Option<String> = std::env::var("PUBLIC_URL").ok()
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty());
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrTarget {
+    Blue,
+    Red,
+    View,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Svg,
+    Png,
+}
+
+#[derive(Deserialize)]
+pub struct QrQuery {
+    #[serde(default)]
+    format: QrFormat,
+}
+
+pub async fn qr_handler(
+    Path((join_code, target)): Path<(JoinCode, QrTarget)>,
+    Query(QrQuery { format }): Query<QrQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    if state.lock().await.get_session_by_join_code(join_code).is_none() {
+        return (StatusCode::BAD_REQUEST, "invalid join code").into_response();
+    }
+
+    let base_url = match &*PUBLIC_URL {
+        Some(url) => url.clone(),
+        None => {
+            let Some(host) = headers.get(header::HOST).and_then(|host| host.to_str().ok()) else {
+                return (StatusCode::BAD_REQUEST, "missing host header").into_response();
+            };
+            let scheme = headers.get("x-forwarded-proto")
+                .filter(|_| *TRUST_PROXY)
+                .and_then(|proto| proto.to_str().ok())
+                .unwrap_or("http");
+            format!("{scheme}://{host}")
+        },
+    };
+    let path = match target {
+        QrTarget::Blue => format!("/join?code={join_code}&team=blue"),
+        QrTarget::Red => format!("/join?code={join_code}&team=red"),
+        QrTarget::View => format!("/view?code={join_code}"),
+    };
+
+    // the host header can be long enough that the url doesn't fit
+    let Ok(code) = QrCode::new(format!("{base_url}{path}")) else {
+        return (StatusCode::BAD_REQUEST, "url is too long for a qr code").into_response();
+    };
+    match format {
+        QrFormat::Svg => {
+            let svg = code.render::<svg::Color>().min_dimensions(256, 256).build();
+            ([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response()
+        },
+        QrFormat::Png => {
+            let mut png = Vec::new();
+            code.render::<Luma<u8>>().min_dimensions(256, 256).build()
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .expect("can encode png");
+            ([(header::CONTENT_TYPE, "image/png")], png).into_response()
+        },
+    }
+}
@@ -1,20 +1,54 @@
-use std::collections::HashMap;
+use std::{collections::{hash_map::Entry, HashMap, HashSet}, error::Error, fmt::{Display, Formatter}, net::IpAddr, time::{Duration, Instant}};
 
+use rand::{thread_rng, Rng};
 use tokio::sync::broadcast::{Receiver, Sender, self};
 use serde::{Deserialize, Serialize};
 
-use crate::{game::{GameData, GameState}, packet::{Writable, PacketWriter}};
+use crate::{game::{GameData, GameState, ScorePoint}, invite::{self, Invite, ScoreboardId}, join_code::JoinCode, packet::{DecodeError, Writable, PacketWriter, Readable, PacketReader}};
+
+/// How many random ids or join codes are tried before giving up on creating a session.
+const MAX_ATTEMPTS: usize = 16;
+/// How many incorrect PINs a single address can enter within [`PIN_FAILURE_WINDOW`] before it is locked out.
+const MAX_PIN_FAILURES: u32 = 5;
+const PIN_FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+pub const MAX_GRACE_PERIOD: Duration = Duration::from_secs(60);
 
 pub struct SessionManager {
     sessions: HashMap<u32, Session>,
+    join_codes: HashMap<JoinCode, u32>,
+    scoreboards: HashMap<ScoreboardId, Scoreboard>,
 }
 
 impl SessionManager {
     pub fn new() -> Self {
-        SessionManager { sessions: HashMap::new() }
+        SessionManager { sessions: HashMap::new(), join_codes: HashMap::new(), scoreboards: HashMap::new() }
+    }
+
+    /// Creates a new session under a random, unused id.
+    pub fn new_session(&mut self, scoreboard: ScoreboardId, blue_teams: Vec<String>, red_teams: Vec<String>, match_number: u16, game_data: GameData) -> Result<(u32, &Session), SessionManagerError> {
+        self.new_session_with(&mut thread_rng(), scoreboard, blue_teams, red_teams, match_number, game_data)
     }
 
-    pub fn new_session(&mut self, id: u32, blue_teams: Vec<String>, red_teams: Vec<String>, match_number: u16, game_data: GameData) -> &Session {
+    /// Creates a new session under an unused id drawn from `rng`.
+    fn new_session_with(&mut self, rng: &mut impl Rng, scoreboard: ScoreboardId, blue_teams: Vec<String>, red_teams: Vec<String>, match_number: u16, game_data: GameData) -> Result<(u32, &Session), SessionManagerError> {
+        let id = (0..MAX_ATTEMPTS)
+            .map(|_| rng.gen())
+            .find(|id| !self.sessions.contains_key(id))
+            .ok_or(SessionManagerError::NoFreeId)?;
+
+        self.insert_session(id, scoreboard, blue_teams, red_teams, match_number, game_data).map(|session| (id, session))
+    }
+
+    /// Creates a new session under `id`, failing if a session with that id is already running.
+    /// The session becomes the one `scoreboard` is running, which its invites join, so it fails too if
+    /// `scoreboard` is already running a session.
+    pub fn insert_session(&mut self, id: u32, scoreboard: ScoreboardId, blue_teams: Vec<String>, red_teams: Vec<String>, match_number: u16, game_data: GameData) -> Result<&Session, SessionManagerError> {
+        if self.get_session_by_scoreboard(scoreboard).is_some() { return Err(SessionManagerError::ScoreboardInUse); }
+        let Entry::Vacant(entry) = self.sessions.entry(id) else { return Err(SessionManagerError::IdTaken(id)) };
+        let join_code = Self::new_join_code(&self.join_codes)?;
+
         let (host_sender, host_recv) = broadcast::channel(512);
         let (user_sender, user_recv) = broadcast::channel(512);
         let (viewer_sender, viewer_recv) = broadcast::channel(512);
@@ -23,7 +57,63 @@ impl SessionManager {
         let user = User { sender: user_sender, recv: user_recv };
         let viewer = Viewer { sender: viewer_sender, recv: viewer_recv };
 
-        self.sessions.entry(id).or_insert(Session { host, user, viewer, blue_teams, red_teams, match_number, game_data, game_state: Default::default() })
+        self.join_codes.insert(join_code, id);
+        self.scoreboards.entry(scoreboard).or_default().session_id = Some(id);
+
+        Ok(entry.insert(Session {
+            host,
+            user,
+            viewer,
+            join_code,
+            scoreboard,
+            blue_teams,
+            red_teams,
+            match_number,
+            game_data,
+            game_state: Default::default(),
+            display_mode: Default::default(),
+            refs: HashMap::new(),
+            next_ref_id: 0,
+            banned_refs: HashSet::new(),
+            scoring_locked: false,
+            score_while_paused: false,
+            ref_pin: generate_pin(),
+            pin_failures: HashMap::new(),
+            review_categories: Vec::new(),
+            pending_scores: HashMap::new(),
+            next_pending_id: 0,
+            consensus_mode: Default::default(),
+            ref_counts: HashMap::new(),
+            discrepancies: HashSet::new(),
+            adjustments: Vec::new(),
+            grace_period: DEFAULT_GRACE_PERIOD,
+            ended_at: None,
+            score_events: Vec::new(),
+            acks: HashMap::new(),
+            client_refs: HashMap::new(),
+            ref_connections: HashMap::new(),
+            next_connection: 0,
+            started_at: None,
+        }))
+    }
+
+    fn new_join_code(join_codes: &HashMap<JoinCode, u32>) -> Result<JoinCode, SessionManagerError> {
+        let mut rng = thread_rng();
+        (0..MAX_ATTEMPTS)
+            .map(|_| JoinCode::random(&mut rng))
+            .find(|join_code| !join_codes.contains_key(join_code))
+            .ok_or(SessionManagerError::NoFreeJoinCode)
+    }
+
+    pub fn get_session_by_join_code(&self, join_code: JoinCode) -> Option<(u32, &Session)> {
+        let id = *self.join_codes.get(&join_code)?;
+        self.sessions.get(&id).map(|session| (id, session))
+    }
+
+    /// The session `scoreboard` is running right now, if any.
+    pub fn get_session_by_scoreboard(&self, scoreboard: ScoreboardId) -> Option<(u32, &Session)> {
+        let id = self.scoreboards.get(&scoreboard)?.session_id?;
+        self.sessions.get(&id).map(|session| (id, session))
     }
 
     pub fn get_session(&self, id: u32) -> Option<&Session> {
@@ -35,7 +125,39 @@ impl SessionManager {
     }
 
     pub fn close_session(&mut self, id: u32) -> Option<Session> {
-        self.sessions.remove(&id).inspect(|session| { let _ = session.user.sender.send(UserMessage::Close); })
+        self.sessions.remove(&id).inspect(|session| {
+            self.join_codes.remove(&session.join_code);
+            if let Entry::Occupied(mut entry) = self.scoreboards.entry(session.scoreboard) {
+                let scoreboard = entry.get_mut();
+                if scoreboard.session_id == Some(id) { scoreboard.session_id = None; }
+                // nothing to remember about a scoreboard that isn't running and never revoked an invite
+                if scoreboard.session_id.is_none() && scoreboard.revoked_invites.is_empty() { entry.remove(); }
+            }
+            let _ = session.user.sender.send(UserMessage::Close);
+        })
+    }
+
+    /// Checks a joining ref's credentials against session `id` and registers them there.
+    /// Returns `None` if the session doesn't exist.
+    pub fn join_ref(&mut self, id: u32, addr: IpAddr, credential: JoinCredential, ref_info: RefInfo) -> Option<Result<JoinedRef, JoinError>> {
+        if let JoinCredential::Invite(invite) = &credential {
+            if self.is_invite_revoked(invite) { return Some(Err(JoinError::InviteRevoked)); }
+        }
+        self.sessions.get_mut(&id).map(|session| session.join_ref(addr, credential, ref_info))
+    }
+
+    /// Revokes an invite of session `id`'s scoreboard for good, returning the refs in the session that joined with it.
+    pub fn revoke_invite(&mut self, id: u32, invite_id: u32) -> Vec<u32> {
+        let Some(session) = self.sessions.get(&id) else { return Vec::new(); };
+        self.scoreboards.entry(session.scoreboard).or_default().revoked_invites.insert(invite_id);
+        session.refs.iter()
+            .filter(|(_, ref_info)| ref_info.invite_id == Some(invite_id))
+            .map(|(ref_id, _)| *ref_id)
+            .collect()
+    }
+
+    pub fn is_invite_revoked(&self, invite: &Invite) -> bool {
+        self.scoreboards.get(&invite.scoreboard).is_some_and(|scoreboard| scoreboard.revoked_invites.contains(&invite.invite_id))
     }
 }
 
@@ -45,15 +167,526 @@ impl Default for SessionManager {
     }
 }
 
+/// What is kept about a scoreboard between its sessions.
+#[derive(Default)]
+struct Scoreboard {
+    /// The session the scoreboard is running now.
+    session_id: Option<u32>,
+    revoked_invites: HashSet<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionManagerError {
+    IdTaken(u32),
+    NoFreeId,
+    NoFreeJoinCode,
+    ScoreboardInUse,
+}
+
+impl Display for SessionManagerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionManagerError::IdTaken(id) => write!(f, "a session with id {id} already exists"),
+            SessionManagerError::NoFreeId => write!(f, "could not find an unused session id"),
+            SessionManagerError::NoFreeJoinCode => write!(f, "could not find an unused join code"),
+            SessionManagerError::ScoreboardInUse => write!(f, "a match is already being hosted on this field"),
+        }
+    }
+}
+
+impl Error for SessionManagerError {}
+
 pub struct Session {
     pub host: Host,
     pub user: User,
     pub viewer: Viewer,
+    pub join_code: JoinCode,
+    pub scoreboard: ScoreboardId,
     pub game_data: GameData,
     pub game_state: GameState,
     pub blue_teams: Vec<String>,
     pub red_teams: Vec<String>,
     pub match_number: u16,
+    pub display_mode: DisplayMode,
+    pub refs: HashMap<u32, RefInfo>,
+    next_ref_id: u32,
+    /// Client ids of the devices banned refs joined from.
+    banned_refs: HashSet<String>,
+    pub scoring_locked: bool,
+    /// Whether refs can keep scoring while the game is paused.
+    pub score_while_paused: bool,
+    pub ref_pin: String,
+    pin_failures: HashMap<IpAddr, PinFailures>,
+    /// Score point categories whose scores wait for the host's approval before counting.
+    pub review_categories: Vec<String>,
+    pub pending_scores: HashMap<u32, RefScore>,
+    next_pending_id: u32,
+    pub consensus_mode: ConsensusMode,
+    /// How many times each ref counted each score point, used to reconcile the official count in consensus mode.
+    ref_counts: HashMap<(Team, u16), HashMap<u32, u32>>,
+    /// Score points where the refs disagree and the host has not resolved it yet.
+    pub discrepancies: HashSet<(Team, u16)>,
+    /// Every change the host made to the score by hand, oldest first.
+    pub adjustments: Vec<ScoreAdjustment>,
+    /// How long after the game ends refs can still score.
+    pub grace_period: Duration,
+    pub ended_at: Option<Instant>,
+    /// Every score made by a ref, indexed by event id.
+    score_events: Vec<ScoreEvent>,
+    /// What was done with each score or undo a ref sent, by client id and sequence number, so retries aren't counted twice.
+    acks: HashMap<(String, u32), ScoreAck>,
+    /// The id and alliance each ref's device last joined as.
+    client_refs: HashMap<String, (u32, Team)>,
+    /// The connection each ref in `refs` joined on, which changes when their device joins again before the old one closes.
+    ref_connections: HashMap<u32, u32>,
+    next_connection: u32,
+    pub started_at: Option<Instant>,
+}
+
+impl Session {
+    /// Checks a joining ref's PIN and registers them, returning the id they are known by for the rest of the session.
+    /// Whether an invite was revoked is checked by [`SessionManager::join_ref`], since revocations outlive the session.
+    fn join_ref(&mut self, addr: IpAddr, credential: JoinCredential, ref_info: RefInfo) -> Result<JoinedRef, JoinError> {
+        match credential {
+            JoinCredential::Pin(pin) => {
+                self.pin_failures.retain(|_, failures| failures.since.elapsed() <= PIN_FAILURE_WINDOW);
+                let failures = self.pin_failures.entry(addr).or_insert(PinFailures { count: 0, since: Instant::now() });
+                if failures.count >= MAX_PIN_FAILURES { return Err(JoinError::RateLimited); }
+
+                if pin != self.ref_pin {
+                    failures.count += 1;
+                    return Err(JoinError::IncorrectPin);
+                }
+            },
+            JoinCredential::Invite(_) => {},
+        }
+        if self.banned_refs.contains(&ref_info.client_id) { return Err(JoinError::Banned); }
+
+        // a ref reconnecting from the same device keeps their id, and with it their scores,
+        // even if their old connection dropped without closing and is still in `refs`
+        let ref_id = match self.client_refs.get(&ref_info.client_id) {
+            Some((ref_id, team)) if *team == ref_info.team => *ref_id,
+            _ => {
+                let ref_id = self.next_ref_id;
+                self.next_ref_id += 1;
+                self.client_refs.insert(ref_info.client_id.clone(), (ref_id, ref_info.team));
+                ref_id
+            },
+        };
+        let replaced = self.refs.insert(ref_id, ref_info).is_some();
+        let connection = self.next_connection;
+        self.next_connection = self.next_connection.wrapping_add(1);
+        self.ref_connections.insert(ref_id, connection);
+        Ok(JoinedRef { ref_id, connection, replaced })
+    }
+
+    /// Removes a ref whose connection closed, unless their device already joined again on a new one.
+    /// Returns whether the ref was removed.
+    pub fn leave_ref(&mut self, ref_id: u32, connection: u32) -> bool {
+        if !self.is_ref_connection(ref_id, connection) { return false; }
+        self.ref_connections.remove(&ref_id);
+        self.refs.remove(&ref_id);
+        true
+    }
+
+    /// Whether `connection` is the one ref `ref_id` is scoring from.
+    pub fn is_ref_connection(&self, ref_id: u32, connection: u32) -> bool {
+        self.ref_connections.get(&ref_id) == Some(&connection)
+    }
+
+    /// Replaces the ref PIN with `pin`, or a random one if `None`.
+    pub fn set_ref_pin(&mut self, pin: Option<String>) -> &str {
+        self.ref_pin = pin.unwrap_or_else(generate_pin);
+        &self.ref_pin
+    }
+
+    /// Stops the device a ref joined from from joining this session again, whatever name it uses.
+    /// Returns the ref's info, or `None` if they aren't in the session.
+    ///
+    /// The device is only known by the client id its browser keeps, so a ref who clears the site's data or
+    /// switches to another browser or device can still join. Change the PIN or lock scoring to keep them out for good.
+    pub fn ban_ref(&mut self, ref_id: u32) -> Option<&RefInfo> {
+        let ref_info = self.refs.get(&ref_id)?;
+        self.banned_refs.insert(ref_info.client_id.clone());
+        Some(ref_info)
+    }
+
+    pub fn needs_review(&self, score_id: u16) -> bool {
+        self.game_data.score_points.get(score_id as usize)
+            .is_some_and(|score_point| self.review_categories.contains(&score_point.category))
+    }
+
+    pub fn add_pending_score(&mut self, pending: RefScore) -> u32 {
+        let pending_id = self.next_pending_id;
+        self.next_pending_id += 1;
+        self.pending_scores.insert(pending_id, pending);
+        pending_id
+    }
+
+    /// Counts a ref's score towards the official count. Returns `None` if nothing changed.
+    pub fn score(&mut self, ref_id: u32, team: Team, score_id: u16, undo: bool) -> Option<ScoreChange> {
+        if self.consensus_mode == ConsensusMode::Off {
+            self.game_state.apply_score(team, score_id, undo);
+            return Some(ScoreChange::Counted);
+        }
+
+        let count = self.ref_counts.entry((team, score_id)).or_default().entry(ref_id).or_default();
+        if undo {
+            if *count == 0 { return None; }
+            *count -= 1;
+        } else {
+            *count += 1;
+        }
+
+        Some(ScoreChange::Reconciled(self.reconcile(team, score_id)))
+    }
+
+    /// Sets the official count of a disputed score point, overriding what the refs counted.
+    pub fn resolve_discrepancy(&mut self, team: Team, score_id: u16, count: u32) -> Option<ScoreCounts> {
+        if self.consensus_mode == ConsensusMode::Off || !self.discrepancies.remove(&(team, score_id)) { return None; }
+
+        let changed = self.game_state.count(team, score_id) != count as i32;
+        self.override_count(team, score_id, count);
+        Some(ScoreCounts { count, changed, disputed: false, ref_counts: self.ref_counts_of(team, score_id) })
+    }
+
+    /// Records a ref's score, returning its event id.
+    pub fn add_score_event(&mut self, ref_id: u32, team: Team, score_id: u16) -> u32 {
+        self.score_events.push(ScoreEvent { ref_id, team, score_id, undone: false });
+        (self.score_events.len() - 1) as u32
+    }
+
+    /// How many of a score point a ref has scored and not undone.
+    pub fn scored_by(&self, ref_id: u32, score_id: u16) -> u32 {
+        self.score_events.iter()
+            .filter(|event| event.ref_id == ref_id && event.score_id == score_id && !event.undone)
+            .count() as u32
+    }
+
+    /// Marks one of a ref's scores as undone, defaulting to their latest one that hasn't been undone.
+    /// Returns the id of the undone event.
+    pub fn undo_score_event(&mut self, ref_id: u32, event_id: Option<u32>) -> Result<(u32, ScoreEvent), UndoError> {
+        let event_id = match event_id {
+            Some(event_id) => event_id,
+            None => self.score_events.iter()
+                .rposition(|event| event.ref_id == ref_id && !event.undone)
+                .ok_or(UndoError::NothingToUndo)? as u32,
+        };
+        let event = *self.score_events.get(event_id as usize).ok_or(UndoError::NothingToUndo)?;
+        if event.ref_id != ref_id { return Err(UndoError::NotYours); }
+        if event.undone { return Err(UndoError::AlreadyUndone); }
+
+        let count = match self.consensus_mode {
+            ConsensusMode::Off => self.game_state.count(event.team, event.score_id),
+            _ => self.ref_counts.get(&(event.team, event.score_id))
+                .and_then(|counts| counts.get(&ref_id))
+                .map_or(0, |count| *count as i32),
+        };
+        if count <= 0 { return Err(UndoError::BelowZero); }
+
+        self.score_events[event_id as usize].undone = true;
+        Ok((event_id, event))
+    }
+
+    /// What was done with a score or undo the ref already sent, if anything.
+    pub fn ack(&self, client_id: &str, seq: u32) -> Option<ScoreAck> {
+        self.acks.get(&(client_id.to_string(), seq)).copied()
+    }
+
+    pub fn add_ack(&mut self, client_id: &str, seq: u32, ack: ScoreAck) {
+        self.acks.insert((client_id.to_string(), seq), ack);
+    }
+
+    /// Reverts what a rejected score or undo did to its event.
+    pub fn reject_score(&mut self, score: &RefScore) {
+        if let Some(event) = self.score_events.get_mut(score.event_id as usize) {
+            event.undone = !score.undo;
+        }
+    }
+
+    /// How long the game has been going by the server's clock, in milliseconds.
+    pub fn match_time(&self) -> Option<u64> {
+        self.started_at.map(|started_at| started_at.elapsed().as_millis() as u64)
+    }
+
+    /// Checks that a score recorded `time` milliseconds into the game could have happened:
+    /// it isn't in the future, during a pause, or after the grace period.
+    pub fn check_match_time(&self, time: u64) -> Result<(), RejectReason> {
+        let now = self.match_time().ok_or(RejectReason::NotStarted)?;
+        if time > now { return Err(RejectReason::InvalidTime); }
+        if self.game_state.time_ended.is_some_and(|time_ended| time > time_ended + self.grace_period.as_millis() as u64) {
+            return Err(RejectReason::InvalidTime);
+        }
+        if !self.score_while_paused && self.game_state.pauses.iter().any(|(start, end)| time >= *start && end.is_none_or(|end| time < end)) {
+            return Err(RejectReason::Paused);
+        }
+        Ok(())
+    }
+
+    /// Whether the game has ended, making any score late.
+    pub fn is_late(&self) -> bool {
+        self.ended_at.is_some()
+    }
+
+    /// Whether refs can no longer score because the score was revealed or the grace period is over.
+    pub fn scoring_closed(&self) -> bool {
+        self.game_state.revealed || self.ended_at.is_some_and(|ended_at| ended_at.elapsed() > self.grace_period)
+    }
+
+    /// Sets the official count of a score point by hand, recording why. Returns the previous count.
+    pub fn adjust_score(&mut self, team: Team, score_id: u16, count: u32, reason: String) -> i32 {
+        let previous = self.game_state.count(team, score_id);
+        self.override_count(team, score_id, count);
+        self.discrepancies.remove(&(team, score_id));
+        self.adjustments.push(ScoreAdjustment { team, score_id, previous, count, reason, time: invite::now() });
+        previous
+    }
+
+    /// Sets the official count for the host. In consensus mode every ref's count is set to it too,
+    /// so the next score builds on the host's count instead of reconciling it away.
+    fn override_count(&mut self, team: Team, score_id: u16, count: u32) {
+        self.game_state.set_score(team, score_id, count);
+        if self.consensus_mode == ConsensusMode::Off { return; }
+        self.counts_of_refs(team, score_id).values_mut().for_each(|ref_count| *ref_count = count);
+    }
+
+    fn reconcile(&mut self, team: Team, score_id: u16) -> ScoreCounts {
+        let counts = self.counts_of_refs(team, score_id);
+        let max = counts.values().max().copied().unwrap_or_default();
+        let agreed = counts.values().all(|count| *count == max);
+        if agreed {
+            self.discrepancies.remove(&(team, score_id));
+        } else {
+            self.discrepancies.insert((team, score_id));
+        }
+
+        let previous = self.game_state.count(team, score_id);
+        let count = match self.consensus_mode {
+            ConsensusMode::Agreement if !agreed => previous.max(0) as u32,
+            _ => max,
+        };
+        self.game_state.set_score(team, score_id, count);
+
+        ScoreCounts { count, changed: previous != count as i32, disputed: !agreed, ref_counts: self.ref_counts_of(team, score_id) }
+    }
+
+    /// Each ref's count of a score point, including the refs who can score it but haven't yet.
+    fn counts_of_refs(&mut self, team: Team, score_id: u16) -> &mut HashMap<u32, u32> {
+        let score_point = self.game_data.score_points.get(score_id as usize);
+        let counts = self.ref_counts.entry((team, score_id)).or_default();
+        // refs on the alliance who haven't counted this score point yet counted zero of it
+        for (ref_id, ref_info) in &self.refs {
+            if ref_info.team == team && score_point.is_some_and(|score_point| ref_info.can_score(score_point)) {
+                counts.entry(*ref_id).or_default();
+            }
+        }
+        counts
+    }
+
+    fn ref_counts_of(&self, team: Team, score_id: u16) -> Vec<(u32, u32)> {
+        self.ref_counts.get(&(team, score_id))
+            .map(|counts| counts.iter().map(|(ref_id, count)| (*ref_id, *count)).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScoreAdjustment {
+    pub team: Team,
+    pub score_id: u16,
+    pub previous: i32,
+    pub count: u32,
+    pub reason: String,
+    /// Unix timestamp in seconds.
+    pub time: u64,
+}
+
+pub enum ScoreChange {
+    /// The score was added straight to the official count.
+    Counted,
+    /// The refs' counts were reconciled into a new official count.
+    Reconciled(ScoreCounts),
+}
+
+pub struct ScoreCounts {
+    pub count: u32,
+    pub changed: bool,
+    pub disputed: bool,
+    pub ref_counts: Vec<(u32, u32)>,
+}
+
+/// A score or undo sent by a ref, on its way to being counted.
+#[derive(Clone, Copy, Debug)]
+pub struct RefScore {
+    /// The score event this scores or undoes.
+    pub event_id: u32,
+    pub ref_id: u32,
+    pub team: Team,
+    pub score_id: u16,
+    pub undo: bool,
+    pub late: bool,
+}
+
+/// A score a ref recorded while they were offline, sent once they reconnect.
+#[derive(Clone, Copy, Debug)]
+pub struct OfflineScore {
+    pub seq: u32,
+    pub score_id: u16,
+    /// When the score was made, in milliseconds since the game started.
+    pub time: u64,
+}
+
+impl Readable for OfflineScore {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let seq = reader.read()?;
+        let score_id = reader.read()?;
+        let time = reader.read()?;
+
+        Ok(OfflineScore { seq, score_id, time })
+    }
+}
+
+/// A score made by a ref, which only that ref can undo.
+#[derive(Clone, Copy, Debug)]
+pub struct ScoreEvent {
+    pub ref_id: u32,
+    pub team: Team,
+    pub score_id: u16,
+    pub undone: bool,
+}
+
+/// The outcome of a score or undo that was counted, sent back to the ref who sent it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScoreAck {
+    /// The score was counted as the event with this id.
+    Scored(u32, u16),
+    /// The event with this id was undone.
+    Undone(u32, u16),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UndoError {
+    NothingToUndo,
+    NotYours,
+    AlreadyUndone,
+    BelowZero,
+}
+
+impl Display for UndoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UndoError::NothingToUndo => write!(f, "nothing to undo"),
+            UndoError::NotYours => write!(f, "that score was made by another ref"),
+            UndoError::AlreadyUndone => write!(f, "that score was already undone"),
+            UndoError::BelowZero => write!(f, "that score was already taken off"),
+        }
+    }
+}
+
+impl Error for UndoError {}
+
+/// Why a packet was refused, sent back instead of closing the connection.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum RejectReason {
+    Malformed,
+    NotStarted,
+    InvalidScorePoint,
+    Locked,
+    RateLimited,
+    NotPermitted,
+    ScoringClosed,
+    NothingToUndo,
+    NotYours,
+    AlreadyUndone,
+    BelowZero,
+    InvalidTime,
+    Paused,
+    /// The score can't be revealed while scores wait for the host's review.
+    PendingReview,
+    /// The score can't be revealed while the refs disagree on it.
+    Disputed,
+    /// A batch of offline scores had more entries than the server takes at once.
+    BatchTooLarge,
+}
+
+impl From<UndoError> for RejectReason {
+    fn from(value: UndoError) -> Self {
+        match value {
+            UndoError::NothingToUndo => RejectReason::NothingToUndo,
+            UndoError::NotYours => RejectReason::NotYours,
+            UndoError::AlreadyUndone => RejectReason::AlreadyUndone,
+            UndoError::BelowZero => RejectReason::BelowZero,
+        }
+    }
+}
+
+impl Writable for RejectReason {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write(self as u8);
+    }
+}
+
+pub enum JoinCredential {
+    Pin(String),
+    Invite(Invite),
+}
+
+fn generate_pin() -> String {
+    format!("{:06}", thread_rng().gen_range(0..1_000_000))
+}
+
+struct PinFailures {
+    count: u32,
+    since: Instant,
+}
+
+/// A ref who joined a session.
+#[derive(Debug, PartialEq, Eq)]
+pub struct JoinedRef {
+    pub ref_id: u32,
+    /// Tells this connection apart from an older one the ref's device joined on, which shares the ref id.
+    pub connection: u32,
+    /// Whether the ref's device was still connected and that connection should be kicked.
+    pub replaced: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum JoinError {
+    IncorrectPin,
+    RateLimited,
+    InviteRevoked,
+    Banned,
+}
+
+impl Display for JoinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::IncorrectPin => write!(f, "incorrect PIN"),
+            JoinError::RateLimited => write!(f, "too many incorrect PINs, try again later"),
+            JoinError::InviteRevoked => write!(f, "invite has been revoked"),
+            JoinError::Banned => write!(f, "banned from this session"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RefInfo {
+    pub name: String,
+    pub role: RefRole,
+    pub team: Team,
+    /// The invite this ref joined with, if they didn't use the PIN.
+    pub invite_id: Option<u32>,
+    /// Score point categories this ref may score. Empty means every category.
+    pub categories: Vec<String>,
+    /// A random id the ref's device picked, which stays the same if they reconnect.
+    pub client_id: String,
+}
+
+impl RefInfo {
+    pub fn can_score(&self, score_point: &ScorePoint) -> bool {
+        self.categories.is_empty() || self.categories.contains(&score_point.category)
+    }
 }
 
 pub struct Host {
@@ -71,29 +704,43 @@ pub struct Viewer {
     pub sender: Sender<ViewerMessage>,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum HostMessage {
-    Score(Team, u8, bool),
+    /// A ref's score, flagged if it came in after the game ended.
+    Score(RefScore),
+    RefJoined(u32, RefInfo),
+    RefLeft(u32),
 }
 
 #[derive(Clone, Copy)]
 pub enum UserMessage {
     Close,
     GameStart,
-    GameEnd,
+    /// The game ended, leaving a grace period in seconds to send late scores.
+    GameEnd(u64),
+    ScoringClosed,
+    ScoringLocked(bool),
+    /// The game was paused or unpaused, and whether refs can score while it's paused.
+    Paused(bool, bool),
+    Kick(u32),
+    RefUpdated(u32),
+    /// The host rejected a score or undo that was waiting for review.
+    ScoreRejected(RefScore),
 }
 
 #[derive(Clone, Copy, Debug)]
 pub enum ViewerMessage {
-    Score(Team, u8, bool),
+    Score(Team, u16, bool),
+    ScoreSet(Team, u16, u32),
     GameStart(u64),
     GameEnd,
     GamePause,
     RevealScore,
     GameUnpause(u64),
+    DisplayMode(DisplayMode),
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum Team {
@@ -101,9 +748,365 @@ pub enum Team {
     Red,
 }
 
+impl Readable for Team {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let offset = reader.offset();
+        match reader.read::<u8>()? {
+            0 => Ok(Team::Blue),
+            1 => Ok(Team::Red),
+            variant => Err(DecodeError::InvalidVariant { name: "team", variant: variant.into(), offset }),
+        }
+    }
+}
+
 impl Writable for Team {
     fn write(self, writer: &mut PacketWriter) {
         writer.write(self as u8);
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum RefRole {
+    Scorer,
+    Penalty,
+    Head,
+}
+
+impl Readable for RefRole {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let offset = reader.offset();
+        match reader.read::<u8>()? {
+            0 => Ok(RefRole::Scorer),
+            1 => Ok(RefRole::Penalty),
+            2 => Ok(RefRole::Head),
+            variant => Err(DecodeError::InvalidVariant { name: "ref role", variant: variant.into(), offset }),
+        }
+    }
+}
+
+impl Writable for RefRole {
+    fn write(self, writer: &mut PacketWriter) {
+        writer.write(self as u8);
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum DisplayMode {
+    Blank,
+    MatchPreview,
+    #[default]
+    Live,
+    Results,
+    Rankings,
+    SponsorSlide,
+}
+
+impl Readable for DisplayMode {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let offset = reader.offset();
+        match reader.read::<u8>()? {
+            0 => Ok(DisplayMode::Blank),
+            1 => Ok(DisplayMode::MatchPreview),
+            2 => Ok(DisplayMode::Live),
+            3 => Ok(DisplayMode::Results),
+            4 => Ok(DisplayMode::Rankings),
+            5 => Ok(DisplayMode::SponsorSlide),
+            variant => Err(DecodeError::InvalidVariant { name: "display mode", variant: variant.into(), offset }),
+        }
+    }
+}
+
+/// How the scores of multiple refs on the same alliance are combined.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[repr(u8)]
+pub enum ConsensusMode {
+    /// Every ref's score adds to the same count.
+    #[default]
+    Off,
+    /// The official count is the highest count of any ref.
+    Max,
+    /// The official count only changes once every ref agrees.
+    Agreement,
+}
+
+impl Readable for ConsensusMode {
+    fn read(reader: &mut PacketReader) -> Result<Self, DecodeError> where Self: Sized {
+        let offset = reader.offset();
+        match reader.read::<u8>()? {
+            0 => Ok(ConsensusMode::Off),
+            1 => Ok(ConsensusMode::Max),
+            2 => Ok(ConsensusMode::Agreement),
+            variant => Err(DecodeError::InvalidVariant { name: "consensus mode", variant: variant.into(), offset }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{game::{GameData, BUILTIN}, invite::{Invite, ScoreboardId}};
+
+    use std::{net::{IpAddr, Ipv4Addr}, time::{Duration, Instant}};
+
+    use rand::rngs::mock::StepRng;
+
+    use super::{ConsensusMode, JoinCredential, JoinError, RefInfo, RefRole, RejectReason, Session, SessionManager, SessionManagerError, Team, UndoError, MAX_PIN_FAILURES, PIN_FAILURE_WINDOW};
+
+    const ADDR: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const SCOREBOARD: ScoreboardId = ScoreboardId(5071);
+
+    fn game_data() -> GameData {
+        BUILTIN.games[0].data.clone()
+    }
+
+    /// A session on its own, for tests that don't need the manager.
+    fn session() -> Session {
+        let mut manager = SessionManager::new();
+        manager.insert_session(7, SCOREBOARD, Vec::new(), Vec::new(), 1, game_data()).unwrap();
+        manager.sessions.remove(&7).unwrap()
+    }
+
+    fn ref_info(name: &str, client_id: &str) -> RefInfo {
+        RefInfo { name: name.to_string(), role: RefRole::Scorer, team: Team::Red, invite_id: None, categories: Vec::new(), client_id: client_id.to_string() }
+    }
+
+    #[test]
+    fn insert_session_rejects_taken_id() {
+        let mut manager = SessionManager::new();
+        manager.insert_session(7, SCOREBOARD, vec!["1".to_string()], vec!["2".to_string()], 1, game_data()).unwrap();
+
+        let err = manager.insert_session(7, ScoreboardId(1), vec!["3".to_string()], vec!["4".to_string()], 2, game_data()).err();
+        assert_eq!(err, Some(SessionManagerError::IdTaken(7)));
+
+        let session = manager.get_session(7).unwrap();
+        assert_eq!(session.blue_teams, ["1"]);
+        assert_eq!(session.red_teams, ["2"]);
+        assert_eq!(session.match_number, 1);
+    }
+
+    #[test]
+    fn rejected_session_does_not_leak_join_code() {
+        let mut manager = SessionManager::new();
+        manager.insert_session(7, SCOREBOARD, Vec::new(), Vec::new(), 1, game_data()).unwrap();
+        assert!(manager.insert_session(7, ScoreboardId(1), Vec::new(), Vec::new(), 2, game_data()).is_err());
+
+        assert_eq!(manager.join_codes.len(), 1);
+    }
+
+    #[test]
+    fn scoreboards_run_one_session_at_a_time() {
+        let mut manager = SessionManager::new();
+        manager.insert_session(7, SCOREBOARD, Vec::new(), Vec::new(), 1, game_data()).unwrap();
+
+        let err = manager.insert_session(8, SCOREBOARD, Vec::new(), Vec::new(), 2, game_data()).err();
+        assert_eq!(err, Some(SessionManagerError::ScoreboardInUse));
+        assert_eq!(manager.get_session_by_scoreboard(SCOREBOARD).map(|(id, _)| id), Some(7));
+        assert_eq!(manager.join_codes.len(), 1);
+    }
+
+    #[test]
+    fn new_session_retries_taken_ids() {
+        let mut manager = SessionManager::new();
+        manager.insert_session(7, SCOREBOARD, vec!["1".to_string()], Vec::new(), 1, game_data()).unwrap();
+
+        // counts up from the taken id, so the first attempt collides
+        let (id, _) = manager.new_session_with(&mut StepRng::new(7, 1), ScoreboardId(1), vec!["3".to_string()], Vec::new(), 2, game_data()).unwrap();
+        assert_eq!(id, 8);
+        assert_eq!(manager.get_session(7).unwrap().blue_teams, ["1"]);
+        assert_eq!(manager.get_session(8).unwrap().blue_teams, ["3"]);
+
+        // only ever draws the taken id
+        let err = manager.new_session_with(&mut StepRng::new(7, 0), ScoreboardId(2), Vec::new(), Vec::new(), 3, game_data()).err();
+        assert_eq!(err, Some(SessionManagerError::NoFreeId));
+        assert_eq!(manager.sessions.len(), 2);
+        assert_eq!(manager.join_codes.len(), 2);
+    }
+
+    #[test]
+    fn closed_session_frees_id_and_join_code() {
+        let mut manager = SessionManager::new();
+        let join_code = manager.insert_session(7, SCOREBOARD, Vec::new(), Vec::new(), 1, game_data()).unwrap().join_code;
+        manager.close_session(7).unwrap();
+
+        assert!(manager.get_session_by_join_code(join_code).is_none());
+        assert!(manager.insert_session(7, SCOREBOARD, Vec::new(), Vec::new(), 2, game_data()).is_ok());
+    }
+
+    #[test]
+    fn banned_refs_cannot_rejoin_under_another_name() {
+        let mut session = session();
+        session.set_ref_pin(Some("1234".to_string()));
+        let pin = || JoinCredential::Pin("1234".to_string());
+
+        let ref_id = session.join_ref(ADDR, pin(), ref_info("A", "device")).unwrap().ref_id;
+        assert!(session.ban_ref(ref_id).is_some());
+        session.refs.remove(&ref_id);
+
+        assert_eq!(session.join_ref(ADDR, pin(), ref_info("B", "device")), Err(JoinError::Banned));
+        assert!(session.join_ref(ADDR, pin(), ref_info("A", "other device")).is_ok());
+    }
+
+    #[test]
+    fn rejoining_device_takes_over_its_stale_connection() {
+        let mut session = session();
+        session.set_ref_pin(Some("1234".to_string()));
+        let pin = || JoinCredential::Pin("1234".to_string());
+
+        let old = session.join_ref(ADDR, pin(), ref_info("A", "device")).unwrap();
+        let event_id = session.add_score_event(old.ref_id, Team::Red, 0);
+        session.score(old.ref_id, Team::Red, 0, false);
+        assert!(!old.replaced);
+
+        // the old connection dropped without closing, so the ref is still in `refs`
+        let new = session.join_ref(ADDR, pin(), ref_info("A", "device")).unwrap();
+        assert_eq!(new.ref_id, old.ref_id);
+        assert!(new.replaced);
+        assert_eq!(session.scored_by(new.ref_id, 0), 1);
+
+        assert!(!session.leave_ref(old.ref_id, old.connection));
+        assert!(session.refs.contains_key(&new.ref_id));
+        assert_eq!(session.undo_score_event(new.ref_id, Some(event_id)).map(|(event_id, _)| event_id), Ok(event_id));
+
+        assert!(session.leave_ref(new.ref_id, new.connection));
+        assert!(session.refs.is_empty());
+    }
+
+    #[test]
+    fn pin_failures_only_lock_out_one_address() {
+        let mut session = session();
+        session.set_ref_pin(Some("1234".to_string()));
+        let pin = |pin: &str| JoinCredential::Pin(pin.to_string());
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        for _ in 0..MAX_PIN_FAILURES {
+            assert_eq!(session.join_ref(ADDR, pin("0000"), ref_info("A", "a")), Err(JoinError::IncorrectPin));
+        }
+        assert_eq!(session.join_ref(ADDR, pin("1234"), ref_info("A", "a")), Err(JoinError::RateLimited));
+        assert!(session.join_ref(other, pin("1234"), ref_info("B", "b")).is_ok());
+
+        // once the window is over the failures are forgotten
+        let failures = session.pin_failures.get_mut(&ADDR).unwrap();
+        failures.since = Instant::now().checked_sub(PIN_FAILURE_WINDOW + Duration::from_secs(1)).unwrap();
+        assert_eq!(session.join_ref(other, pin("0000"), ref_info("B", "b")), Err(JoinError::IncorrectPin));
+        assert!(!session.pin_failures.contains_key(&ADDR));
+        assert!(session.join_ref(ADDR, pin("1234"), ref_info("A", "a")).is_ok());
+    }
+
+    #[test]
+    fn invites_follow_their_scoreboard_across_sessions() {
+        let mut manager = SessionManager::new();
+        let invite = |invite_id| JoinCredential::Invite(Invite { scoreboard: SCOREBOARD, invite_id, team: Team::Red, categories: Vec::new(), expires_at: u64::MAX });
+        let invited = |name: &str, invite_id| RefInfo { invite_id: Some(invite_id), ..ref_info(name, name) };
+
+        manager.insert_session(7, SCOREBOARD, Vec::new(), Vec::new(), 1, game_data()).unwrap();
+        let ref_id = manager.join_ref(7, ADDR, invite(1), invited("A", 1)).unwrap().unwrap().ref_id;
+        assert_eq!(manager.revoke_invite(7, 1), [ref_id]);
+        manager.close_session(7);
+        assert!(manager.get_session_by_scoreboard(SCOREBOARD).is_none());
+
+        // the scoreboard's next match takes over its invites, revocations included
+        manager.insert_session(8, SCOREBOARD, Vec::new(), Vec::new(), 2, game_data()).unwrap();
+        assert_eq!(manager.get_session_by_scoreboard(SCOREBOARD).map(|(id, _)| id), Some(8));
+        assert_eq!(manager.join_ref(8, ADDR, invite(1), invited("A", 1)), Some(Err(JoinError::InviteRevoked)));
+        assert!(manager.join_ref(8, ADDR, invite(2), invited("B", 2)).unwrap().is_ok());
+
+        // scoreboards are forgotten once they have nothing left to remember
+        manager.insert_session(9, ScoreboardId(1), Vec::new(), Vec::new(), 1, game_data()).unwrap();
+        manager.close_session(9);
+        manager.close_session(8);
+        assert_eq!(manager.scoreboards.keys().collect::<Vec<_>>(), [&SCOREBOARD]);
+    }
+
+    #[test]
+    fn max_consensus_uses_highest_count() {
+        let mut session = session();
+        session.consensus_mode = ConsensusMode::Max;
+
+        session.score(0, Team::Red, 0, false);
+        session.score(1, Team::Red, 0, false);
+        session.score(0, Team::Red, 0, false);
+
+        assert_eq!(session.game_state.count(Team::Red, 0), 2);
+        assert!(session.discrepancies.contains(&(Team::Red, 0)));
+
+        session.score(1, Team::Red, 0, false);
+        assert_eq!(session.game_state.count(Team::Red, 0), 2);
+        assert!(session.discrepancies.is_empty());
+    }
+
+    #[test]
+    fn agreement_consensus_waits_for_every_ref() {
+        let mut session = session();
+        session.consensus_mode = ConsensusMode::Agreement;
+
+        session.score(0, Team::Blue, 0, false);
+        session.score(1, Team::Blue, 0, false);
+        session.score(0, Team::Blue, 0, false);
+        assert_eq!(session.game_state.count(Team::Blue, 0), 1);
+
+        let counts = session.resolve_discrepancy(Team::Blue, 0, 2).unwrap();
+        assert!(counts.changed);
+        assert_eq!(session.game_state.count(Team::Blue, 0), 2);
+        assert!(session.discrepancies.is_empty());
+
+        // the refs' counts follow the host's so the next score doesn't undo the resolution
+        session.score(1, Team::Blue, 0, false);
+        session.score(0, Team::Blue, 0, false);
+        assert_eq!(session.game_state.count(Team::Blue, 0), 3);
+        assert!(session.discrepancies.is_empty());
+    }
+
+    #[test]
+    fn host_adjustments_survive_the_next_ref_score() {
+        let mut session = session();
+        session.consensus_mode = ConsensusMode::Max;
+
+        session.score(0, Team::Red, 0, false);
+        session.score(0, Team::Red, 0, false);
+        session.adjust_score(Team::Red, 0, 5, "missed scores".to_string());
+
+        session.score(0, Team::Red, 0, false);
+        assert_eq!(session.game_state.count(Team::Red, 0), 6);
+    }
+
+    #[test]
+    fn refs_only_undo_their_own_scores() {
+        let mut session = session();
+
+        let first = session.add_score_event(0, Team::Red, 0);
+        session.score(0, Team::Red, 0, false);
+        let second = session.add_score_event(1, Team::Red, 0);
+        session.score(1, Team::Red, 0, false);
+
+        assert_eq!(session.undo_score_event(0, Some(second)).err(), Some(UndoError::NotYours));
+        assert_eq!(session.undo_score_event(0, None).map(|(event_id, _)| event_id), Ok(first));
+        assert_eq!(session.undo_score_event(0, Some(first)).err(), Some(UndoError::AlreadyUndone));
+        assert_eq!(session.undo_score_event(0, None).err(), Some(UndoError::NothingToUndo));
+
+        session.adjust_score(Team::Red, 0, 0, "miscount".to_string());
+        assert_eq!(session.undo_score_event(1, None).err(), Some(UndoError::BelowZero));
+    }
+
+    #[test]
+    fn offline_scores_must_be_during_the_game() {
+        let mut session = session();
+        assert_eq!(session.check_match_time(0), Err(RejectReason::NotStarted));
+
+        session.started_at = Some(Instant::now() - Duration::from_secs(60));
+        session.game_state.pauses.push((10_000, Some(20_000)));
+        assert_eq!(session.check_match_time(5_000), Ok(()));
+        assert_eq!(session.check_match_time(15_000), Err(RejectReason::Paused));
+        assert_eq!(session.check_match_time(25_000), Ok(()));
+        assert_eq!(session.check_match_time(120_000), Err(RejectReason::InvalidTime));
+
+        session.score_while_paused = true;
+        assert_eq!(session.check_match_time(15_000), Ok(()));
+
+        session.game_state.time_ended = Some(30_000);
+        session.grace_period = Duration::from_secs(5);
+        assert_eq!(session.check_match_time(34_000), Ok(()));
+        assert_eq!(session.check_match_time(40_000), Err(RejectReason::InvalidTime));
+    }
+}
@@ -1,47 +1,178 @@
-use std::time::Duration;
+use std::{net::{IpAddr, SocketAddr}, time::{Duration, Instant}};
 
-use axum::{extract::{Path, State, WebSocketUpgrade, ws::{Message, WebSocket}}, http::StatusCode, response::{IntoResponse, Response}};
-use tokio::{sync::{broadcast::{Receiver, Sender}, mpsc}, time::MissedTickBehavior};
+use axum::{extract::{ConnectInfo, Path, Query, State, WebSocketUpgrade, ws::{close_code, CloseFrame, Message, WebSocket}}, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use tokio::{sync::{broadcast::{Receiver, Sender}, mpsc}, time::{MissedTickBehavior, timeout}};
 use futures::{SinkExt, StreamExt};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use crate::{session_manager::{Team, HostMessage, UserMessage}, AppState, packet::{ServerboundUserPacket, ClientboundUserPacket, IntoBytes, FromBytes}, game::GameData};
+use crate::{session_manager::{Team, HostMessage, JoinCredential, JoinedRef, OfflineScore, RefInfo, RefScore, RejectReason, ScoreAck, ScoreEvent, Session, UserMessage}, AppState, packet::{ServerboundUserPacket, ClientboundUserPacket, DecodeLimits, IntoBytes, FromBytes, OUTDATED_REASON, PROTOCOL_VERSION}, join_code::JoinCode, invite::{Invite, InviteError}, game::GameData};
+
+const MAX_NAME_LEN: usize = 32;
+const MAX_CLIENT_ID_LEN: usize = 64;
+const MAX_BATCH_LEN: usize = 256;
+
+const RATE_LIMIT: u32 = 10;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    /// Set `TRUST_PROXY` when the server is behind a reverse proxy, so refs are told apart (e.g. when limiting PIN
+    /// attempts) by the address the proxy saw instead of the proxy's own, and QR codes link with the scheme it was reached by.
+    pub static ref TRUST_PROXY: bool = std::env::var("TRUST_PROXY").is_ok_and(|trust| trust == "1" || trust.eq_ignore_ascii_case("true"));
+}
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
-    Path((id, team)): Path<(u32, Team)>,
+    Path((join_code, team)): Path<(JoinCode, Team)>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(JoinQuery { invite }): Query<JoinQuery>,
+    headers: HeaderMap,
     State(state): State<AppState>,
 ) -> Response {
+    let addr = client_addr(addr, &headers);
+    let invite = match invite.map(|invite| Invite::verify(&invite)).transpose() {
+        Ok(invite) => invite,
+        Err(err) => {
+            warn!("{addr} used an invalid invite: {err}");
+            return (StatusCode::FORBIDDEN, err.to_string()).into_response();
+        },
+    };
+
     let res = {
         let lock = state.lock().await;
-        let session = lock.get_session(id);
-        session.map(|session| {
-            (session.game_state.time_started.is_some(), session.game_data.clone(), session.host.sender.clone(), session.user.sender.subscribe())
+        let session = lock.get_session_by_join_code(join_code);
+        session.map(|(id, session)| {
+            let invite_err = invite.as_ref().and_then(|invite| {
+                if invite.scoreboard != session.scoreboard || invite.team != team { Some(InviteError::WrongScoreboard) }
+                else if lock.is_invite_revoked(invite) { Some(InviteError::Revoked) }
+                else { None }
+            });
+            (id, invite_err, session.host.sender.clone(), session.user.sender.subscribe())
         })
     };
-    let (started, game_data, host_sender, user_recv) = if let Some((started, game_data, host_sender, user_recv)) = res {
-        (started, game_data, host_sender, user_recv)
+    let (id, invite_err, host_sender, user_recv) = if let Some((id, invite_err, host_sender, user_recv)) = res {
+        (id, invite_err, host_sender, user_recv)
     } else {
-        return (StatusCode::BAD_REQUEST, "invalid session id").into_response();
+        return (StatusCode::BAD_REQUEST, "invalid join code").into_response();
     };
+    if let Some(err) = invite_err {
+        warn!("[{id}] {addr} used an invalid invite: {err}");
+        return (StatusCode::FORBIDDEN, err.to_string()).into_response();
+    }
 
     info!("[{id}] user connected");
 
-    ws.on_upgrade(move |ws| handle_upgrade(ws, UserInfo { session_id: id, team }, started, game_data, host_sender, user_recv, state)).into_response()
+    ws.max_message_size(DecodeLimits::current().max_packet_len)
+        .on_upgrade(move |ws| handle_socket(ws, id, UserInfo { team, addr, invite }, host_sender, user_recv, state))
+        .into_response()
+}
+
+/// The address a ref connected from, as seen by the reverse proxy if it is trusted.
+fn client_addr(addr: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    if !*TRUST_PROXY { return addr.ip(); }
+
+    // the proxy appends the address it saw, so anything before it may have been made up by the client
+    headers.get_all("x-forwarded-for").iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .and_then(|forwarded| forwarded.trim().parse().ok())
+        .unwrap_or(addr.ip())
+}
+
+#[derive(Deserialize)]
+pub struct JoinQuery {
+    invite: Option<String>,
+}
+
+async fn handle_socket(
+    mut ws: WebSocket,
+    id: u32,
+    UserInfo { team, addr, invite }: UserInfo,
+    host_sender: Sender<HostMessage>,
+    user_recv: Receiver<UserMessage>,
+    state: AppState,
+) {
+    if let Ok(Some(Ok(Message::Binary(bytes)))) = timeout(Duration::from_secs(3), async { ws.recv().await }).await {
+        let outdated = match ServerboundUserPacket::from_bytes(bytes) {
+            Ok(ServerboundUserPacket::Join { version: PROTOCOL_VERSION, name, role, pin, client_id }) => {
+                let name = name.trim().to_string();
+                if !name.is_empty() && name.chars().count() <= MAX_NAME_LEN && !client_id.is_empty() && client_id.len() <= MAX_CLIENT_ID_LEN {
+                    let invite_id = invite.as_ref().map(|invite| invite.invite_id);
+                    let categories = invite.as_ref().map(|invite| invite.categories.clone()).unwrap_or_default();
+                    let credential = match invite {
+                        Some(invite) => JoinCredential::Invite(invite),
+                        None => JoinCredential::Pin(pin.trim().to_string()),
+                    };
+                    let ref_info = RefInfo { name, role, team, invite_id, categories, client_id };
+                    handle_upgrade(ws, id, JoinRequest { addr, credential, ref_info }, host_sender, user_recv, state).await;
+                    return;
+                }
+                None
+            },
+            Ok(ServerboundUserPacket::Join { version, .. }) => Some(format!("version {version}")),
+            Ok(_) => None,
+            // a first packet that can't be read most likely came from an old cached page
+            Err(err) => Some(err.to_string()),
+        };
+        if let Some(reason) = outdated {
+            info!("[{id}] user is outdated ({reason})");
+            let _ = ws.send(Message::Binary(ClientboundUserPacket::Outdated(PROTOCOL_VERSION).into_bytes())).await;
+            let _ = ws.send(close_message(OUTDATED_REASON)).await;
+            return;
+        }
+    }
+
+    info!("[{id}] user did not join");
+    let _ = ws.close().await;
 }
 
 async fn handle_upgrade(
     mut ws: WebSocket,
-    UserInfo { session_id: id, team }: UserInfo,
-    started: bool,
-    game_data: GameData,
+    id: u32,
+    JoinRequest { addr, credential, ref_info }: JoinRequest,
     host_sender: Sender<HostMessage>,
     mut user_recv: Receiver<UserMessage>,
     state: AppState,
 ) {
-    if let Err(err) = ws.send(Message::Binary(ClientboundUserPacket::SessionInfo(started, game_data).into_bytes())).await {
+    let team = ref_info.team;
+    let client_id = ref_info.client_id.clone();
+    let res = {
+        let mut lock = state.lock().await;
+        lock.join_ref(id, addr, credential, ref_info.clone()).map(|res| {
+            res.map(|JoinedRef { ref_id, connection, replaced }| {
+                let session = lock.get_session(id).expect("session exists");
+                if replaced {
+                    info!("[{id}] ref {ref_id} joined again before their old connection closed");
+                    let _ = session.user.sender.send(UserMessage::Kick(ref_id));
+                    // the kick is for the old connection, which shares the ref id
+                    user_recv = user_recv.resubscribe();
+                }
+                (ref_id, connection, session_info(session, ref_id))
+            })
+        })
+    };
+    let (ref_id, connection, info_packet) = match res {
+        Some(Ok(res)) => res,
+        Some(Err(err)) => {
+            warn!("[{id}] {} ({addr}) could not join: {err}", ref_info.name);
+            let _ = ws.send(close_message(err.to_string())).await;
+            return;
+        },
+        None => {
+            let _ = ws.close().await;
+            return;
+        },
+    };
+
+    info!("[{id}] ref {ref_id} joined as {} ({:?} {:?})", ref_info.name, ref_info.team, ref_info.role);
+    let _ = host_sender.send(HostMessage::RefJoined(ref_id, ref_info));
+
+    if let Err(err) = ws.send(Message::Binary(info_packet.into_bytes())).await {
         info!("[{id}] could not send user score info. {err:?}");
-        ws.close().await.expect("can close ws");
+        let _ = ws.close().await;
+        leave(id, ref_id, connection, &host_sender, &state).await;
         return;
     }
 
@@ -50,7 +181,8 @@ async fn handle_upgrade(
 
     let ws_send_task = async {
         while let Some(message) = ws_recv.recv().await {
-            if sender.send(message).await.is_err() { break; }
+            let close = matches!(message, Message::Close(_));
+            if sender.send(message).await.is_err() || close { break; }
         }
     };
 
@@ -69,7 +201,28 @@ async fn handle_upgrade(
         while let Ok(message) = user_recv.recv().await {
             let bytes = match message {
                 UserMessage::GameStart => Some(ClientboundUserPacket::StartGame().into_bytes()),
-                UserMessage::GameEnd => Some(ClientboundUserPacket::EndGame().into_bytes()),
+                UserMessage::GameEnd(grace_period) => Some(ClientboundUserPacket::EndGame(grace_period).into_bytes()),
+                UserMessage::ScoringClosed => Some(ClientboundUserPacket::ScoringClosed().into_bytes()),
+                UserMessage::ScoringLocked(locked) => Some(ClientboundUserPacket::ScoringLocked(locked).into_bytes()),
+                UserMessage::Paused(paused, score_while_paused) => Some(ClientboundUserPacket::Paused(paused, score_while_paused).into_bytes()),
+                UserMessage::Kick(kicked_id) if kicked_id == ref_id => {
+                    let replaced = state.lock().await.get_session(id).is_some_and(|session| !session.is_ref_connection(ref_id, connection));
+                    let reason = if replaced { "joined again from this device" } else { "kicked by the host" };
+                    info!("[{id}] ref {ref_id} was kicked ({reason})");
+                    if ws_sender.send(close_message(reason)).is_err() { break; }
+                    continue;
+                },
+                UserMessage::Kick(_) => continue,
+                UserMessage::RefUpdated(updated_id) if updated_id == ref_id => {
+                    let lock = state.lock().await;
+                    let Some(session) = lock.get_session(id) else { break; };
+                    Some(session_info(session, ref_id).into_bytes())
+                },
+                UserMessage::RefUpdated(_) => continue,
+                UserMessage::ScoreRejected(score) if score.ref_id == ref_id => {
+                    Some(ClientboundUserPacket::ScoreRejected(score.event_id, score.score_id, score.undo).into_bytes())
+                },
+                UserMessage::ScoreRejected(_) => continue,
                 UserMessage::Close => None,
             };
 
@@ -82,28 +235,62 @@ async fn handle_upgrade(
     };
 
     let recv_task = async {
+        let mut rate_limit = RateLimit { count: 0, since: Instant::now() };
         while let Some(message) = recv.next().await {
             match message {
                 Ok(Message::Binary(bytes)) => {
-                    let message = {
-                        match ServerboundUserPacket::from_bytes(bytes) {
-                            Some(ServerboundUserPacket::Score { score_type, undo }) => {
-                                let can_score = {
-                                    let lock = state.lock().await;
-                                    let session = lock.get_session(id).expect("session exists");
-                                    let started = session.game_state.time_started.is_some();
-
-                                    started && (score_type as usize) < session.game_data.score_points.len()
-                                };
-
-                                if can_score { HostMessage::Score(team, score_type, undo) }
-                                else { break; }
+                    if !rate_limit.allow() {
+                        if ws_sender.send(rejected(None, RejectReason::RateLimited)).is_err() { break; }
+                        continue;
+                    }
+
+                    let packet = match ServerboundUserPacket::from_bytes(bytes) {
+                        Ok(packet) => packet,
+                        Err(err) => {
+                            warn!("[{id}] ref {ref_id} sent a malformed packet: {err}");
+                            if ws_sender.send(Message::Binary(ClientboundUserPacket::Malformed(err.to_string()).into_bytes())).is_err() { break; }
+                            continue;
+                        },
+                    };
+
+                    let replies = {
+                        let mut lock = state.lock().await;
+                        let session = lock.get_session_mut(id).expect("session exists");
+                        match packet {
+                            ServerboundUserPacket::Score { seq, score_type } => vec![(Some(seq), score(session, ref_id, &client_id, team, seq, score_type))],
+                            ServerboundUserPacket::Undo { seq, event_id } => vec![(Some(seq), undo(session, ref_id, &client_id, team, seq, event_id))],
+                            ServerboundUserPacket::ScoreBatch { entries } if entries.len() <= MAX_BATCH_LEN => {
+                                info!("[{id}] ref {ref_id} sent {} offline scores", entries.len());
+                                entries.into_iter()
+                                    .map(|entry| (Some(entry.seq), offline_score(session, ref_id, &client_id, team, entry)))
+                                    .collect()
+                            },
+                            // each entry is rejected on its own so the ref knows which scores weren't counted
+                            ServerboundUserPacket::ScoreBatch { entries } => {
+                                warn!("[{id}] ref {ref_id} sent {} offline scores at once", entries.len());
+                                entries.into_iter().map(|entry| (Some(entry.seq), Err(RejectReason::BatchTooLarge))).collect()
+                            },
+                            ServerboundUserPacket::Join { .. } => {
+                                warn!("[{id}] ref {ref_id} sent an unexpected packet");
+                                vec![(None, Err(RejectReason::Malformed))]
                             },
-                            None => break,
                         }
                     };
 
-                    if host_sender.send(message).is_err() { break; }
+                    let mut connected = true;
+                    for (seq, res) in replies {
+                        let (packet, ref_score) = match (seq, res) {
+                            (Some(seq), Ok((ScoreAck::Scored(event_id, score_id), ref_score))) => (ClientboundUserPacket::ScoreAck(seq, event_id, score_id), ref_score),
+                            (Some(seq), Ok((ScoreAck::Undone(event_id, score_id), ref_score))) => (ClientboundUserPacket::UndoAck(seq, event_id, score_id), ref_score),
+                            (seq, res) => (ClientboundUserPacket::Rejected(seq, res.err().unwrap_or(RejectReason::Malformed)), None),
+                        };
+                        connected &= ws_sender.send(Message::Binary(packet.into_bytes())).is_ok();
+                        // no score means it was rejected or a retry of one that was already counted
+                        if let Some(ref_score) = ref_score {
+                            connected &= host_sender.send(HostMessage::Score(ref_score)).is_ok();
+                        }
+                    }
+                    if !connected { break; }
                 },
                 Ok(_) => {},
                 Err(err) => error!("[user {id}] {err}"),
@@ -118,11 +305,223 @@ async fn handle_upgrade(
         _ = recv_task => {},
     };
 
-    info!("[{id}] user disconnected");
+    leave(id, ref_id, connection, &host_sender, &state).await;
+}
+
+/// Checks that refs can score or undo right now.
+fn scoring_open(session: &Session) -> Result<(), RejectReason> {
+    if session.scoring_closed() { return Err(RejectReason::ScoringClosed); }
+    if session.game_state.time_started.is_none() && !session.game_state.ended { return Err(RejectReason::NotStarted); }
+    if session.scoring_locked { return Err(RejectReason::Locked); }
+    if session.game_state.paused && !session.score_while_paused { return Err(RejectReason::Paused); }
+    Ok(())
+}
+
+/// Records a ref's score if they are allowed to make it and it wasn't already counted.
+/// Returns the ack to send back and, if it is new, the score to pass on to the host.
+fn score(session: &mut Session, ref_id: u32, client_id: &str, team: Team, seq: u32, score_id: u16) -> Result<(ScoreAck, Option<RefScore>), RejectReason> {
+    if let Some(ack) = session.ack(client_id, seq) { return Ok((ack, None)); }
+    scoring_open(session)?;
+
+    let late = session.is_late();
+    add_score(session, ref_id, client_id, team, seq, score_id, late)
+}
+
+/// Records a score a ref made while offline, like [`score`], if it was made while the game was running.
+fn offline_score(session: &mut Session, ref_id: u32, client_id: &str, team: Team, OfflineScore { seq, score_id, time }: OfflineScore) -> Result<(ScoreAck, Option<RefScore>), RejectReason> {
+    if let Some(ack) = session.ack(client_id, seq) { return Ok((ack, None)); }
+    if session.game_state.revealed { return Err(RejectReason::ScoringClosed); }
+    if session.scoring_locked { return Err(RejectReason::Locked); }
+    session.check_match_time(time)?;
+
+    let late = session.game_state.time_ended.is_some_and(|time_ended| time > time_ended);
+    add_score(session, ref_id, client_id, team, seq, score_id, late)
+}
+
+fn add_score(session: &mut Session, ref_id: u32, client_id: &str, team: Team, seq: u32, score_id: u16, late: bool) -> Result<(ScoreAck, Option<RefScore>), RejectReason> {
+    let score_point = session.game_data.score_points.get(score_id as usize).ok_or(RejectReason::InvalidScorePoint)?;
+    if !session.refs.get(&ref_id).is_some_and(|ref_info| ref_info.can_score(score_point)) { return Err(RejectReason::NotPermitted); }
+
+    let event_id = session.add_score_event(ref_id, team, score_id);
+    let ack = ScoreAck::Scored(event_id, score_id);
+    session.add_ack(client_id, seq, ack);
+    Ok((ack, Some(RefScore { event_id, ref_id, team, score_id, undo: false, late })))
+}
+
+/// Undoes one of a ref's scores if it wasn't already undone by this same packet, like [`score`].
+fn undo(session: &mut Session, ref_id: u32, client_id: &str, team: Team, seq: u32, event_id: Option<u32>) -> Result<(ScoreAck, Option<RefScore>), RejectReason> {
+    if let Some(ack) = session.ack(client_id, seq) { return Ok((ack, None)); }
+    scoring_open(session)?;
+
+    let (event_id, ScoreEvent { score_id, .. }) = session.undo_score_event(ref_id, event_id)?;
+    let ack = ScoreAck::Undone(event_id, score_id);
+    session.add_ack(client_id, seq, ack);
+    Ok((ack, Some(RefScore { event_id, ref_id, team, score_id, undo: true, late: session.is_late() })))
+}
+
+/// Limits how many packets a ref can send in a short time.
+struct RateLimit {
+    count: u32,
+    since: Instant,
+}
+
+impl RateLimit {
+    fn allow(&mut self) -> bool {
+        if self.since.elapsed() > RATE_LIMIT_WINDOW {
+            self.count = 0;
+            self.since = Instant::now();
+        }
+        self.count += 1;
+        self.count <= RATE_LIMIT
+    }
+}
+
+fn rejected(seq: Option<u32>, reason: RejectReason) -> Message {
+    Message::Binary(ClientboundUserPacket::Rejected(seq, reason).into_bytes())
+}
+
+/// The session info for a ref, containing only the score points they are allowed to score and how many of each they have scored.
+fn session_info(session: &Session, ref_id: u32) -> ClientboundUserPacket {
+    let ref_info = session.refs.get(&ref_id);
+    let (score_ids, score_points): (Vec<u16>, Vec<_>) = session.game_data.score_points.iter()
+        .enumerate()
+        .filter(|(_, score_point)| ref_info.is_some_and(|ref_info| ref_info.can_score(score_point)))
+        .map(|(score_id, score_point)| (score_id as u16, score_point.clone()))
+        .unzip();
+    let game_data = GameData { duration: session.game_data.duration.clone(), score_points: score_points.into() };
+
+    let counts = score_ids.iter().map(|score_id| session.scored_by(ref_id, *score_id)).collect();
+
+    let state = &session.game_state;
+    let started = state.time_started.is_some() && !session.scoring_closed();
+    let elapsed = session.match_time().unwrap_or_default();
+    ClientboundUserPacket::SessionInfo(started, state.ended, state.paused, session.score_while_paused, elapsed, session.scoring_locked, score_ids, counts, game_data)
 }
 
 struct UserInfo {
-    session_id: u32,
     team: Team,
+    addr: IpAddr,
+    invite: Option<Invite>,
+}
+
+struct JoinRequest {
+    addr: IpAddr,
+    credential: JoinCredential,
+    ref_info: RefInfo,
+}
+
+fn close_message(reason: impl Into<String>) -> Message {
+    Message::Close(Some(CloseFrame { code: close_code::POLICY, reason: reason.into().into() }))
+}
+
+async fn leave(id: u32, ref_id: u32, connection: u32, host_sender: &Sender<HostMessage>, state: &AppState) {
+    let replaced = {
+        let mut lock = state.lock().await;
+        lock.get_session_mut(id).is_some_and(|session| !session.leave_ref(ref_id, connection))
+    };
+    // a connection the ref's device replaced closing doesn't take the ref with it
+    if replaced {
+        info!("[{id}] ref {ref_id}'s old connection closed");
+        return;
+    }
+    let _ = host_sender.send(HostMessage::RefLeft(ref_id));
+
+    info!("[{id}] ref {ref_id} disconnected");
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::{game::BUILTIN, invite::ScoreboardId, session_manager::{OfflineScore, RefInfo, RefRole, RejectReason, ScoreAck, Session, SessionManager, Team}};
+
+    use std::time::{Duration, Instant};
+
+    use super::{offline_score, score, undo};
+
+    const CLIENT: &str = "device";
+
+    /// A running session with ref 0 on the red alliance, who can score anything.
+    fn session() -> Session {
+        let mut manager = SessionManager::new();
+        manager.insert_session(7, ScoreboardId(5071), Vec::new(), Vec::new(), 1, BUILTIN.games[0].data.clone()).unwrap();
+        let mut session = manager.close_session(7).unwrap();
+        session.refs.insert(0, RefInfo { name: "A".to_string(), role: RefRole::Scorer, team: Team::Red, invite_id: None, categories: Vec::new(), client_id: CLIENT.to_string() });
+        session.game_state.time_started = Some(0);
+        session.started_at = Some(Instant::now() - Duration::from_secs(10));
+        session
+    }
+
+    #[test]
+    fn retried_packets_are_only_counted_once() {
+        let mut session = session();
+
+        let (ack, counted) = score(&mut session, 0, CLIENT, Team::Red, 1, 0).unwrap();
+        assert_eq!(ack, ScoreAck::Scored(0, 0));
+        assert!(counted.is_some());
+        // as the host does once it gets the score
+        session.score(0, Team::Red, 0, false);
+        assert_eq!(score(&mut session, 0, CLIENT, Team::Red, 1, 0).map(|(ack, counted)| (ack, counted.is_some())), Ok((ack, false)));
+
+        // a ref who reconnected under a new ref id is still the same client
+        session.refs.insert(1, session.refs[&0].clone());
+        assert_eq!(score(&mut session, 1, CLIENT, Team::Red, 1, 0).map(|(ack, counted)| (ack, counted.is_some())), Ok((ack, false)));
+        assert_eq!(session.scored_by(0, 0), 1);
+
+        let (ack, counted) = undo(&mut session, 0, CLIENT, Team::Red, 2, None).unwrap();
+        assert_eq!(ack, ScoreAck::Undone(0, 0));
+        assert!(counted.is_some());
+        assert_eq!(undo(&mut session, 0, CLIENT, Team::Red, 2, None).map(|(ack, counted)| (ack, counted.is_some())), Ok((ack, false)));
+
+        let offline = OfflineScore { seq: 3, score_id: 0, time: 1_000 };
+        let (ack, _) = offline_score(&mut session, 0, CLIENT, Team::Red, offline).unwrap();
+        assert_eq!(offline_score(&mut session, 0, CLIENT, Team::Red, offline).map(|(ack, counted)| (ack, counted.is_some())), Ok((ack, false)));
+        assert_eq!(session.scored_by(0, 0), 1);
+    }
+
+    #[test]
+    fn scores_are_rejected_while_scoring_is_not_open() {
+        let mut session = session();
+
+        session.game_state.paused = true;
+        assert_eq!(score(&mut session, 0, CLIENT, Team::Red, 1, 0).err(), Some(RejectReason::Paused));
+        session.score_while_paused = true;
+        assert!(score(&mut session, 0, CLIENT, Team::Red, 2, 0).is_ok());
+
+        session.scoring_locked = true;
+        assert_eq!(score(&mut session, 0, CLIENT, Team::Red, 3, 0).err(), Some(RejectReason::Locked));
+        assert_eq!(undo(&mut session, 0, CLIENT, Team::Red, 3, None).err(), Some(RejectReason::Locked));
+        let offline = OfflineScore { seq: 3, score_id: 0, time: 1_000 };
+        assert_eq!(offline_score(&mut session, 0, CLIENT, Team::Red, offline).err(), Some(RejectReason::Locked));
+        session.scoring_locked = false;
+
+        session.game_state.ended = true;
+        session.game_state.revealed = true;
+        assert_eq!(score(&mut session, 0, CLIENT, Team::Red, 4, 0).err(), Some(RejectReason::ScoringClosed));
+        assert_eq!(offline_score(&mut session, 0, CLIENT, Team::Red, offline).err(), Some(RejectReason::ScoringClosed));
+
+        // the grace period for late scores runs out too
+        session.game_state.revealed = false;
+        session.grace_period = Duration::from_secs(5);
+        session.ended_at = Some(Instant::now() - Duration::from_secs(1));
+        assert!(score(&mut session, 0, CLIENT, Team::Red, 5, 0).is_ok_and(|(_, counted)| counted.is_some_and(|counted| counted.late)));
+        session.ended_at = Some(Instant::now() - Duration::from_secs(6));
+        assert_eq!(score(&mut session, 0, CLIENT, Team::Red, 6, 0).err(), Some(RejectReason::ScoringClosed));
+    }
+
+    #[test]
+    fn rejected_packets_are_not_acked() {
+        let mut session = session();
+
+        session.scoring_locked = true;
+        assert_eq!(score(&mut session, 0, CLIENT, Team::Red, 1, 0).err(), Some(RejectReason::Locked));
+        assert_eq!(session.ack(CLIENT, 1), None);
+
+        // so the same packet counts once it is allowed
+        session.scoring_locked = false;
+        assert!(score(&mut session, 0, CLIENT, Team::Red, 1, 0).is_ok_and(|(_, counted)| counted.is_some()));
+        assert_eq!(session.ack(CLIENT, 1), Some(ScoreAck::Scored(0, 0)));
+
+        assert_eq!(score(&mut session, 0, CLIENT, Team::Red, 2, u16::MAX).err(), Some(RejectReason::InvalidScorePoint));
+        assert_eq!(session.ack(CLIENT, 2), None);
+    }
+}
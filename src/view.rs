@@ -5,29 +5,30 @@ use serde::Serialize;
 use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 use tracing::info;
 
-use crate::{AppState, game::{GameData, GameState}, session_manager::{ViewerMessage, Team}};
+use crate::{AppState, game::{GameData, GameState}, join_code::JoinCode, packet::PROTOCOL_VERSION, session_manager::{DisplayMode, ViewerMessage, Team}};
 
 pub async fn sse_handler(
-    Path(session_id): Path<u32>,
+    Path(join_code): Path<JoinCode>,
     State(state): State<AppState>,
 ) -> Response {
     let res = {
         let lock = state.lock().await;
-        lock.get_session(session_id).map(|session| {
+        lock.get_session_by_join_code(join_code).map(|(session_id, session)| {
             let recv = session.viewer.sender.subscribe();
             let blue_teams = session.blue_teams.clone();
             let red_teams = session.red_teams.clone();
             let match_number = session.match_number;
             let data = session.game_data.clone();
-            let state = session.game_state.clone();
-            (recv, blue_teams, red_teams, match_number, data, state)
+            let state = Box::new(session.game_state.clone());
+            let display_mode = session.display_mode;
+            (session_id, recv, blue_teams, red_teams, match_number, data, state, display_mode)
         })
     };
 
-    if let Some((viewer_recv, blue_teams, red_teams, match_number, data, state)) = res {
+    if let Some((session_id, viewer_recv, blue_teams, red_teams, match_number, data, state, display_mode)) = res {
         info!("[{session_id}] viewer connected");
 
-        let init_event = ViewerEvent::SessionInfo { blue_teams, red_teams, match_number, data, state };
+        let init_event = ViewerEvent::SessionInfo { version: PROTOCOL_VERSION, blue_teams, red_teams, match_number, data, state, display_mode };
 
         let stream = BroadcastStream::new(viewer_recv)
             .map(|viewer_message| {
@@ -38,31 +39,35 @@ pub async fn sse_handler(
             .merge(stream);
         Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)).text("keep-alive-text")).into_response()
     } else {
-        (StatusCode::BAD_REQUEST, "invalid session id").into_response()
+        (StatusCode::BAD_REQUEST, "invalid join code").into_response()
     }
 }
 
 #[derive(Serialize)]
 #[serde(tag = "type", content = "content", rename_all = "snake_case")]
 enum ViewerEvent {
-    SessionInfo { match_number: u16, blue_teams: Vec<String>, red_teams: Vec<String>, state: GameState, data: GameData },
-    Score { team: Team, score_id: u8, undo: bool },
+    SessionInfo { version: u16, match_number: u16, blue_teams: Vec<String>, red_teams: Vec<String>, state: Box<GameState>, data: GameData, display_mode: DisplayMode },
+    Score { team: Team, score_id: u16, undo: bool },
+    ScoreSet { team: Team, score_id: u16, count: u32 },
     GameStart { time_started: u64 },
     GameEnd,
     RevealScore,
     GamePause,
     GameUnpause { paused_time: u64 },
+    DisplayMode { mode: DisplayMode },
 }
 
 impl From<ViewerMessage> for ViewerEvent {
     fn from(value: ViewerMessage) -> Self {
         match value {
             ViewerMessage::Score(team, score_id, undo) => Self::Score { team, score_id, undo },
+            ViewerMessage::ScoreSet(team, score_id, count) => Self::ScoreSet { team, score_id, count },
             ViewerMessage::GameStart(time_started) => Self::GameStart { time_started },
             ViewerMessage::GameEnd => Self::GameEnd,
             ViewerMessage::GamePause => Self::GamePause,
             ViewerMessage::RevealScore => Self::RevealScore,
-            ViewerMessage::GameUnpause(paused_time) => Self::GameUnpause { paused_time }
+            ViewerMessage::GameUnpause(paused_time) => Self::GameUnpause { paused_time },
+            ViewerMessage::DisplayMode(mode) => Self::DisplayMode { mode },
         }
     }
 }